@@ -6,4 +6,17 @@ pub fn read_file(fname: &str) -> Vec<String> {
         .split('\n')
         .map(|s| String::from(s.trim()))
         .collect();
-}
\ No newline at end of file
+}
+
+// SplitMix64's finalizer
+fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+// Each trial's own seed. Mixing both parts keeps nearby seeds from sharing trials.
+pub fn get_trial_seed(seed: u64, trial: u64) -> u64 {
+    mix(mix(seed) ^ trial)
+}
@@ -2,8 +2,14 @@ extern crate clap;
 extern crate colored;
 use clap::{App, Arg};
 use colored::*;
-use common;
-use std::io::stdin;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::thread;
+use std::time::Duration;
 
 #[derive(Clone)]
 struct BoardPosition<'a> {
@@ -11,7 +17,18 @@ struct BoardPosition<'a> {
     checked: bool,
 }
 
-fn update_boards(number: &str, boards: &mut Vec<Vec<Vec<BoardPosition>>>) {
+#[derive(Clone, Copy, PartialEq)]
+enum Theme {
+    Dark,
+    Light,
+    // No escape codes at all; marks are shown with brackets instead
+    Plain,
+}
+
+// z-score for a 95% confidence interval
+const Z_95: f64 = 1.96;
+
+fn update_boards(number: &str, boards: &mut [Vec<Vec<BoardPosition>>]) {
     for board in boards {
         for row in board {
             for pos in row {
@@ -23,7 +40,7 @@ fn update_boards(number: &str, boards: &mut Vec<Vec<Vec<BoardPosition>>>) {
     }
 }
 
-fn calc_unchecked_sum(board: &Vec<Vec<BoardPosition>>) -> i32 {
+fn calc_unchecked_sum(board: &[Vec<BoardPosition>]) -> i32 {
     let mut unchecked_sum: i32 = 0;
     for row in board {
         for pos in row {
@@ -38,14 +55,10 @@ fn calc_unchecked_sum(board: &Vec<Vec<BoardPosition>>) -> i32 {
     unchecked_sum
 }
 
-fn is_win(board: &Vec<Vec<BoardPosition>>) -> bool {
-    for i in 0..5 {
-        let mut row_win = true;
-        let mut col_win = true;
-        for j in 0..5 {
-            row_win &= board[i][j].checked;
-            col_win &= board[j][i].checked;
-        }
+fn is_win(board: &[Vec<BoardPosition>]) -> bool {
+    for (i, row) in board.iter().enumerate() {
+        let row_win = row.iter().all(|pos| pos.checked);
+        let col_win = board.iter().all(|row| row[i].checked);
 
         if row_win || col_win {
             return true;
@@ -55,15 +68,67 @@ fn is_win(board: &Vec<Vec<BoardPosition>>) -> bool {
     false
 }
 
-fn find_winner(board: &Vec<Vec<BoardPosition>>) -> i32 {
+fn find_winner(board: &[Vec<BoardPosition>]) -> i32 {
     if is_win(board) {
         return calc_unchecked_sum(board);
     }
 
-    return -1;
+    -1
+}
+
+// Cells that are part of a completed row or column
+fn get_winning_cells(board: &[Vec<BoardPosition>]) -> Vec<Vec<bool>> {
+    let mut cells = vec![vec![false; 5]; 5];
+    for i in 0..5 {
+        if board[i].iter().all(|pos| pos.checked) {
+            cells[i] = vec![true; 5];
+        }
+        if board.iter().all(|row| row[i].checked) {
+            for row in cells.iter_mut() {
+                row[i] = true;
+            }
+        }
+    }
+    cells
+}
+
+fn format_position(pos: &BoardPosition, drawn: bool, winning: bool, theme: Theme) -> String {
+    let number = format!("{:>2}", pos.number);
+    if theme == Theme::Plain {
+        let (open, close) = if drawn {
+            ('>', '<')
+        } else if winning {
+            ('[', ']')
+        } else if pos.checked {
+            ('(', ')')
+        } else {
+            (' ', ' ')
+        };
+        return format!("{}{}{}", open, number, close);
+    }
+
+    let styled = match (theme, drawn, winning, pos.checked) {
+        (_, true, _, _) => number.black().on_yellow().bold(),
+        (Theme::Dark, _, true, _) => number.black().on_bright_green().bold(),
+        (_, _, true, _) => number.white().on_green().bold(),
+        (Theme::Dark, _, _, true) => number.bright_green().bold(),
+        (_, _, _, true) => number.green().bold(),
+        (Theme::Dark, _, _, false) => number.bright_black(),
+        (_, _, _, false) => number.black(),
+    };
+    format!(" {} ", styled)
 }
 
-fn print_board(board: &Vec<Vec<BoardPosition>>) {
+fn format_row(board: &[Vec<BoardPosition>], row: usize, drawn: &str, theme: Theme) -> String {
+    let winning_cells = get_winning_cells(board);
+    board[row]
+        .iter()
+        .enumerate()
+        .map(|(col, pos)| format_position(pos, pos.number == drawn, winning_cells[row][col], theme))
+        .collect()
+}
+
+fn print_board(board: &[Vec<BoardPosition>]) {
     for row in board {
         for pos in row {
             print!(
@@ -80,7 +145,43 @@ fn print_board(board: &Vec<Vec<BoardPosition>>) {
     println!();
 }
 
-fn get_input(lines: &Vec<String>) -> (Vec<&str>, Vec<Vec<Vec<BoardPosition>>>) {
+// Prints the boards side by side, `per_row` at a time, with the current draw highlighted
+fn print_boards(
+    boards: &[Vec<Vec<BoardPosition>>],
+    drawn: &str,
+    win_draws: &[Option<usize>],
+    per_row: usize,
+    theme: Theme,
+) {
+    let headers: Vec<String> = win_draws
+        .iter()
+        .enumerate()
+        .map(|(i, win_draw)| match win_draw {
+            Some(draw) => format!("Board {} (won on {})", i, draw + 1),
+            None => format!("Board {}", i),
+        })
+        .collect();
+    // A row is five cells four characters wide, but long headers widen every column
+    let width = headers.iter().map(|h| h.len()).max().unwrap_or(0).max(20);
+    let padding = " ".repeat(width - 20);
+    for (chunk, chunk_headers) in boards.chunks(per_row).zip(headers.chunks(per_row)) {
+        let chunk_headers: Vec<String> = chunk_headers
+            .iter()
+            .map(|header| format!("{:<width$}", header, width = width))
+            .collect();
+        println!("{}", chunk_headers.join("   "));
+        for row in 0..5 {
+            let rows: Vec<String> = chunk
+                .iter()
+                .map(|board| format_row(board, row, drawn, theme) + &padding)
+                .collect();
+            println!("{}", rows.join("   "));
+        }
+        println!();
+    }
+}
+
+fn get_input(lines: &[String]) -> (Vec<&str>, Vec<Vec<Vec<BoardPosition<'_>>>>) {
     let number_draw: &String = &lines[0];
     let numbers_drawn: Vec<&str> = number_draw.split(',').collect();
 
@@ -101,36 +202,79 @@ fn get_input(lines: &Vec<String>) -> (Vec<&str>, Vec<Vec<Vec<BoardPosition>>>) {
             ];
             5
         ];
-        let mut row_index = 0;
-        for j in i..i + 5 {
-            let line = &lines[j];
+        for (board_row, line) in current_board.iter_mut().zip(&lines[i..i + 5]) {
             let row: Vec<&str> = line.split(' ').filter(|&n| !n.is_empty()).collect();
-            for num in 0..5 {
-                current_board[row_index][num] = BoardPosition {
-                    number: row[num],
+            for (pos, &number) in board_row.iter_mut().zip(&row[..5]) {
+                *pos = BoardPosition {
+                    number,
                     checked: false,
                 };
             }
-            row_index += 1;
         }
 
         boards.push(current_board);
         i += 6;
     }
 
-    return (numbers_drawn, boards);
+    (numbers_drawn, boards)
+}
+
+// Plays every number in order and returns, for each board, the index of the draw that made it win
+fn play_game(numbers_drawn: &[&str], boards: &[Vec<Vec<BoardPosition>>]) -> Vec<Option<usize>> {
+    let mut boards = boards.to_vec();
+    let mut win_draws: Vec<Option<usize>> = vec![None; boards.len()];
+    for (draw, number) in numbers_drawn.iter().enumerate() {
+        update_boards(number, &mut boards);
+        for (board, win_draw) in boards.iter().zip(win_draws.iter_mut()) {
+            if win_draw.is_none() && is_win(board) {
+                *win_draw = Some(draw);
+            }
+        }
+        if win_draws.iter().all(|w| w.is_some()) {
+            break;
+        }
+    }
+
+    win_draws
+}
+
+// The first winner is the lowest-indexed board to win on the earliest draw, the last winner the
+// highest-indexed board to win on the latest draw. Boards that never win are neither.
+fn get_first_last_winners(win_draws: &[Option<usize>]) -> (Option<usize>, Option<usize>) {
+    let winners = || {
+        win_draws
+            .iter()
+            .enumerate()
+            .filter_map(|(i, w)| w.map(|draw| (i, draw)))
+    };
+    (
+        winners().min_by_key(|&(_, draw)| draw).map(|(i, _)| i),
+        winners().max_by_key(|&(_, draw)| draw).map(|(i, _)| i),
+    )
 }
 
-fn p1(lines: &Vec<String>) {
+// Wilson score interval for a binomial proportion. No trials says nothing, so anything goes.
+fn wilson_interval(successes: u64, trials: u64) -> (f64, f64) {
+    if trials == 0 {
+        return (0.0, 1.0);
+    }
+    let n = trials as f64;
+    let p = successes as f64 / n;
+    let z2 = Z_95 * Z_95;
+    let center = (p + z2 / (2.0 * n)) / (1.0 + z2 / n);
+    let margin = Z_95 * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt() / (1.0 + z2 / n);
+    ((center - margin).max(0.0), (center + margin).min(1.0))
+}
+
+fn p1(lines: &[String]) {
     let (numbers_drawn, mut boards) = get_input(lines);
     for number in numbers_drawn {
         update_boards(number, &mut boards);
-        for i in 0..boards.len() {
-            let board = &boards[i];
-            let unchecked_sum = find_winner(&board);
+        for (i, board) in boards.iter().enumerate() {
+            let unchecked_sum = find_winner(board);
             if unchecked_sum > -1 {
                 println!("Winning board: {}", i);
-                print_board(&board);
+                print_board(board);
                 println!(
                     "{} • {} = {}",
                     unchecked_sum,
@@ -145,7 +289,7 @@ fn p1(lines: &Vec<String>) {
     println!("No winners!");
 }
 
-fn p2(lines: &Vec<String>) {
+fn p2(lines: &[String]) {
     let (numbers_drawn, mut boards) = get_input(lines);
     for number in numbers_drawn {
         update_boards(number, &mut boards);
@@ -168,6 +312,369 @@ fn p2(lines: &Vec<String>) {
     }
 }
 
+fn replay(lines: &[String], delay: u64, per_row: usize, theme: Theme) {
+    if per_row == 0 {
+        eprintln!("Bad arg value: need at least one board per row");
+        return;
+    }
+    let (numbers_drawn, mut boards) = get_input(lines);
+    let mut win_draws: Vec<Option<usize>> = vec![None; boards.len()];
+    for (draw, number) in numbers_drawn.iter().enumerate() {
+        update_boards(number, &mut boards);
+        for (board, win_draw) in boards.iter().zip(win_draws.iter_mut()) {
+            if win_draw.is_none() && is_win(board) {
+                *win_draw = Some(draw);
+            }
+        }
+
+        // Plain output is meant for logs and pipes, so frames are appended instead of redrawn
+        if theme != Theme::Plain {
+            print!("\x1B[2J\x1B[H");
+        }
+        println!("Draw {}/{}: {}", draw + 1, numbers_drawn.len(), number);
+        println!();
+        print_boards(&boards, number, &win_draws, per_row, theme);
+        io::stdout().flush().unwrap();
+
+        if win_draws.iter().all(|w| w.is_some()) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(delay));
+    }
+
+    match get_first_last_winners(&win_draws) {
+        (Some(first), Some(last)) => println!("First winner: {}, last winner: {}", first, last),
+        _ => println!("No winners!"),
+    }
+}
+
+fn montecarlo(lines: &[String], trials: u64, seed: u64) {
+    if trials == 0 {
+        eprintln!("Bad arg value: need at least one trial");
+        return;
+    }
+    let (numbers_drawn, boards) = get_input(lines);
+    let results: Vec<(Option<usize>, Option<usize>)> = (0..trials)
+        .into_par_iter()
+        .map(|trial| {
+            let mut rng = StdRng::seed_from_u64(common::get_trial_seed(seed, trial));
+            let mut draw_order = numbers_drawn.clone();
+            draw_order.shuffle(&mut rng);
+            get_first_last_winners(&play_game(&draw_order, &boards))
+        })
+        .collect();
+
+    let mut first_wins = vec![0u64; boards.len()];
+    let mut last_wins = vec![0u64; boards.len()];
+    for (first, last) in results {
+        if let Some(i) = first {
+            first_wins[i] += 1;
+        }
+        if let Some(i) = last {
+            last_wins[i] += 1;
+        }
+    }
+
+    println!("{} trials (seed {})", trials, seed);
+    for i in 0..boards.len() {
+        let (first_low, first_high) = wilson_interval(first_wins[i], trials);
+        let (last_low, last_high) = wilson_interval(last_wins[i], trials);
+        println!(
+            "Board {}: first {:.2}% [{:.2}%, {:.2}%]\tlast {:.2}% [{:.2}%, {:.2}%]",
+            i,
+            100.0 * first_wins[i] as f64 / trials as f64,
+            100.0 * first_low,
+            100.0 * first_high,
+            100.0 * last_wins[i] as f64 / trials as f64,
+            100.0 * last_low,
+            100.0 * last_high
+        );
+    }
+}
+
+fn get_bits(mut mask: u128) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        if mask == 0 {
+            return None;
+        }
+        let bit = mask.trailing_zeros() as usize;
+        mask &= mask - 1;
+        Some(bit)
+    })
+}
+
+// Each row and column of a board as a bitmask of draw indices. Lines with a number that is never
+// drawn can't be completed, so they're left out.
+fn get_line_masks(numbers_drawn: &[&str], board: &[Vec<BoardPosition>]) -> Vec<u128> {
+    let get_mask = |line: Vec<&BoardPosition>| -> Option<u128> {
+        line.iter().try_fold(0u128, |mask, pos| {
+            numbers_drawn
+                .iter()
+                .position(|&n| n == pos.number)
+                .map(|i| mask | (1u128 << i))
+        })
+    };
+    let mut masks: Vec<u128> = Vec::new();
+    for i in 0..5 {
+        masks.extend(get_mask(board[i].iter().collect()));
+        masks.extend(get_mask(board.iter().map(|row| &row[i]).collect()));
+    }
+    masks
+}
+
+// Every minimal set of allowed numbers that leaves each of `lines` incomplete
+fn get_hitting_sets(lines: &[u128], allowed: u128) -> HashSet<u128> {
+    let mut sets: HashSet<u128> = HashSet::new();
+    add_hitting_sets(lines, allowed, 0, &mut HashSet::new(), &mut sets);
+    sets
+}
+
+// Branches on the open line with the fewest allowed numbers. A set reached again in another
+// order, or one containing a set already found, can't lead anywhere new.
+fn add_hitting_sets(
+    lines: &[u128],
+    allowed: u128,
+    current: u128,
+    seen: &mut HashSet<u128>,
+    sets: &mut HashSet<u128>,
+) {
+    if !seen.insert(current) || sets.iter().any(|&set| set & !current == 0) {
+        return;
+    }
+    match lines
+        .iter()
+        .filter(|&&line| line & current == 0)
+        .min_by_key(|&&line| (line & allowed).count_ones())
+    {
+        Some(&line) => {
+            for bit in get_bits(line & allowed) {
+                add_hitting_sets(lines, allowed, current | (1u128 << bit), seen, sets);
+            }
+        }
+        None => {
+            let is_minimal = get_bits(current).all(|bit| {
+                let without = current & !(1u128 << bit);
+                lines.iter().any(|&line| line & without == 0)
+            });
+            if is_minimal {
+                sets.insert(current);
+            }
+        }
+    }
+}
+
+// Greedily completes a line on every other board, cheapest first, without drawing any number in
+// `held_back`. Boards after the target must finish before `last` is drawn, earlier boards may tie.
+// Whether it fails is exact (some board has no line left to complete), but the numbers drawn are
+// only a short cover, not necessarily the shortest.
+fn cover_other_boards(
+    target: usize,
+    board_lines: &[Vec<u128>],
+    mut drawn: u128,
+    last: u128,
+    held_back: u128,
+) -> Result<u128, usize> {
+    let mut pending: Vec<usize> = (0..board_lines.len())
+        .filter(|&i| i != target && !board_lines[i].is_empty())
+        .collect();
+    while !pending.is_empty() {
+        let mut cheapest: Option<(usize, u128)> = None;
+        for &i in &pending {
+            let forbidden = if i > target {
+                held_back | last
+            } else {
+                held_back
+            };
+            let line = board_lines[i]
+                .iter()
+                .filter(|&&line| line & forbidden == 0)
+                .min_by_key(|&&line| (line & !drawn).count_ones())
+                .ok_or(i)?;
+            if cheapest
+                .is_none_or(|(_, c)| (line & !drawn).count_ones() < (c & !drawn).count_ones())
+            {
+                cheapest = Some((i, *line));
+            }
+        }
+        let (_, line) = cheapest.unwrap();
+        drawn |= line;
+        pending.retain(|&i| {
+            let forbidden = if i > target {
+                held_back | last
+            } else {
+                held_back
+            };
+            !board_lines[i]
+                .iter()
+                .any(|&line| line & forbidden == 0 && line & !drawn == 0)
+        });
+    }
+
+    Ok(drawn)
+}
+
+// Searches every line/final number/held-back set for the target. Returns the shortest set of
+// numbers drawn found for when the target wins and the number that wins it, or the boards that
+// blocked every attempt. Covers are greedy, so an earlier win may still exist.
+fn plan_last_win(target: usize, board_lines: &[Vec<u128>]) -> Result<(u128, usize), Vec<usize>> {
+    let target_lines = &board_lines[target];
+    let attempts: Vec<(u128, usize)> = target_lines
+        .iter()
+        .flat_map(|&line| get_bits(line).map(move |bit| (line, bit)))
+        .collect();
+    let results: Vec<Result<(u128, usize), Vec<usize>>> = attempts
+        .par_iter()
+        .map(|&(line, bit)| {
+            let last = 1u128 << bit;
+            let other_lines: Vec<u128> = target_lines
+                .iter()
+                .filter(|&&l| l & last == 0)
+                .copied()
+                .collect();
+            let mut best: Option<u128> = None;
+            let mut blockers: Vec<usize> = Vec::new();
+            for held_back in get_hitting_sets(&other_lines, !line) {
+                match cover_other_boards(target, board_lines, line, last, held_back) {
+                    Ok(drawn) => {
+                        if best.is_none_or(|b| drawn.count_ones() < b.count_ones()) {
+                            best = Some(drawn);
+                        }
+                    }
+                    Err(blocker) => blockers.push(blocker),
+                }
+            }
+            best.map(|drawn| (drawn, bit)).ok_or(blockers)
+        })
+        .collect();
+
+    let mut best: Option<(u128, usize)> = None;
+    let mut blocker_counts: HashMap<usize, usize> = HashMap::new();
+    for result in results {
+        match result {
+            Ok((drawn, bit)) => {
+                if best.is_none_or(|(b, _)| drawn.count_ones() < b.count_ones()) {
+                    best = Some((drawn, bit));
+                }
+            }
+            Err(blockers) => {
+                for blocker in blockers {
+                    *blocker_counts.entry(blocker).or_insert(0) += 1;
+                }
+            }
+        }
+    }
+
+    best.ok_or_else(|| {
+        let most_blocks = blocker_counts.values().max().copied().unwrap_or(0);
+        let mut blockers: Vec<usize> = blocker_counts
+            .into_iter()
+            .filter(|&(_, count)| count == most_blocks)
+            .map(|(i, _)| i)
+            .collect();
+        blockers.sort_unstable();
+        blockers
+    })
+}
+
+// Drawing one of the target's lines wins on the fifth draw, unless an earlier board has the same
+// line and takes the tie. If every line is shared that way, no order can help.
+fn plan_first_win(target: usize, board_lines: &[Vec<u128>]) -> Result<(u128, usize), Vec<usize>> {
+    board_lines[target]
+        .iter()
+        .find(|&&line| {
+            !board_lines[..target]
+                .iter()
+                .any(|other| other.iter().any(|&l| l & !line == 0))
+        })
+        .map(|&line| (line, get_bits(line).last().unwrap()))
+        .ok_or_else(|| {
+            (0..target)
+                .filter(|&i| {
+                    board_lines[i]
+                        .iter()
+                        .any(|&l| board_lines[target].contains(&l))
+                })
+                .collect()
+        })
+}
+
+// Draws `drawn` first (keeping the original order), with `last` as the final number of that
+// stretch, then everything else
+fn get_draw_order<'a>(numbers_drawn: &[&'a str], drawn: u128, last: usize) -> Vec<&'a str> {
+    let before = drawn & !(1u128 << last);
+    let mut order: Vec<&str> = get_bits(before).map(|i| numbers_drawn[i]).collect();
+    order.push(numbers_drawn[last]);
+    order.extend(
+        (0..numbers_drawn.len())
+            .filter(|&i| drawn & (1u128 << i) == 0)
+            .map(|i| numbers_drawn[i]),
+    );
+    order
+}
+
+fn adversary(lines: &[String], target: usize, goal: &str) {
+    let (numbers_drawn, boards) = get_input(lines);
+    if target >= boards.len() {
+        eprintln!("No board {} (there are {})", target, boards.len());
+        return;
+    }
+    if numbers_drawn.len() > 128 {
+        eprintln!("Can only plan with up to 128 numbers drawn");
+        return;
+    }
+    let board_lines: Vec<Vec<u128>> = boards
+        .iter()
+        .map(|board| get_line_masks(&numbers_drawn, board))
+        .collect();
+
+    let plan = match goal {
+        "first" => plan_first_win(target, &board_lines),
+        "last" => plan_last_win(target, &board_lines),
+        _ => {
+            eprintln!("Bad goal value: {}", goal);
+            return;
+        }
+    };
+
+    match plan {
+        Ok((drawn, last)) => {
+            let order = get_draw_order(&numbers_drawn, drawn, last);
+            let win_draws = play_game(&order, &boards);
+            let (first, last) = get_first_last_winners(&win_draws);
+            let winner = if goal == "first" { first } else { last };
+            if winner != Some(target) {
+                eprintln!("Planned order doesn't make board {} win {}", target, goal);
+                return;
+            }
+            // Winning first on a line's fifth number can't be beaten, the greedy last plan can
+            println!(
+                "Board {} wins {} on draw {}{}",
+                target,
+                goal,
+                win_draws[target].unwrap() + 1,
+                if goal == "first" {
+                    ""
+                } else {
+                    " (earliest found, not proven earliest)"
+                }
+            );
+            println!("{}", order.join(","));
+        }
+        Err(blockers) if blockers.is_empty() => {
+            println!(
+                "Board {} can never win {}: it has no line that can be completed",
+                target, goal
+            );
+        }
+        Err(blockers) => {
+            println!(
+                "Board {} can never win {}, blocked by board(s) {:?}",
+                target, goal, blockers
+            );
+        }
+    }
+}
+
 fn main() {
     let matches = App::new("day4")
         .version("1.0")
@@ -178,19 +685,203 @@ fn main() {
                 .short("p")
                 .long("part")
                 .value_name("PART")
-                .help("Which part"),
+                .help("Which part (1, 2, replay, montecarlo, adversary)"),
+        )
+        .arg(
+            Arg::with_name("trials")
+                .short("t")
+                .long("trials")
+                .value_name("TRIALS")
+                .default_value("10000")
+                .help("# of shuffled draw orders to simulate (montecarlo)"),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .short("s")
+                .long("seed")
+                .value_name("SEED")
+                .default_value("0")
+                .help("RNG seed (montecarlo)"),
+        )
+        .arg(
+            Arg::with_name("board")
+                .short("b")
+                .long("board")
+                .value_name("BOARD")
+                .default_value("0")
+                .help("Board to make win (adversary)"),
+        )
+        .arg(
+            Arg::with_name("goal")
+                .short("g")
+                .long("goal")
+                .value_name("GOAL")
+                .default_value("first")
+                .possible_values(&["first", "last"])
+                .help("Whether the board should win first or last (adversary)"),
+        )
+        .arg(
+            Arg::with_name("delay")
+                .long("delay")
+                .value_name("MS")
+                .default_value("500")
+                .help("Milliseconds between draws (replay)"),
+        )
+        .arg(
+            Arg::with_name("per-row")
+                .long("per-row")
+                .value_name("BOARDS")
+                .default_value("5")
+                .help("Boards printed side by side (replay)"),
+        )
+        .arg(
+            Arg::with_name("theme")
+                .long("theme")
+                .value_name("THEME")
+                .default_value("dark")
+                .possible_values(&["dark", "light"])
+                .help("Terminal background the colors should suit (replay)"),
+        )
+        .arg(
+            Arg::with_name("no-color")
+                .long("no-color")
+                .takes_value(false)
+                .help("Disable colors and mark cells with brackets (replay)"),
         )
         .get_matches();
 
     let part = matches
         .value_of("part")
         .expect("Part (-p, --part) required.");
+    let trials = matches.value_of("trials").unwrap().parse::<u64>().unwrap();
+    let seed = matches.value_of("seed").unwrap().parse::<u64>().unwrap();
+    let board = matches.value_of("board").unwrap().parse::<usize>().unwrap();
+    let goal = matches.value_of("goal").unwrap();
+    let delay = matches.value_of("delay").unwrap().parse::<u64>().unwrap();
+    let per_row = matches
+        .value_of("per-row")
+        .unwrap()
+        .parse::<usize>()
+        .unwrap();
+    let theme = if matches.is_present("no-color") {
+        Theme::Plain
+    } else if matches.value_of("theme").unwrap() == "light" {
+        Theme::Light
+    } else {
+        Theme::Dark
+    };
 
     let lines: Vec<String> =
         common::read_file("/Users/alexeyler/Development/aoc/2021/day4/src/data/cards.txt");
     match part {
         "1" => p1(&lines),
         "2" => p2(&lines),
+        "replay" => replay(&lines, delay, per_row, theme),
+        "montecarlo" => montecarlo(&lines, trials, seed),
+        "adversary" => adversary(&lines, board, goal),
         _ => eprintln!("Bad arg value: {}", part),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_data(name: &str) -> Vec<String> {
+        common::read_file(&format!("{}/src/data/{}", env!("CARGO_MANIFEST_DIR"), name))
+    }
+
+    fn get_board_lines(
+        numbers_drawn: &[&str],
+        boards: &[Vec<Vec<BoardPosition>>],
+    ) -> Vec<Vec<u128>> {
+        boards
+            .iter()
+            .map(|board| get_line_masks(numbers_drawn, board))
+            .collect()
+    }
+
+    // Plays the planned order and checks the target really comes out first or last
+    fn assert_plan_works(lines: &[String], target: usize, goal: &str) {
+        let (numbers_drawn, boards) = get_input(lines);
+        let board_lines = get_board_lines(&numbers_drawn, &boards);
+        let plan = match goal {
+            "first" => plan_first_win(target, &board_lines),
+            _ => plan_last_win(target, &board_lines),
+        };
+        let (drawn, last) = plan.unwrap_or_else(|b| panic!("board {} blocked by {:?}", target, b));
+        let order = get_draw_order(&numbers_drawn, drawn, last);
+        let (first, last) = get_first_last_winners(&play_game(&order, &boards));
+        assert_eq!(if goal == "first" { first } else { last }, Some(target));
+    }
+
+    // Boards 0 and 1 are the same, board 2 shares only its top row with them. Numbers 1-45 are
+    // drawn in order.
+    fn get_crafted_lines() -> Vec<String> {
+        let board = |numbers: Vec<u32>| -> Vec<String> {
+            let mut lines: Vec<String> = numbers
+                .chunks(5)
+                .map(|row| {
+                    row.iter()
+                        .map(|n| n.to_string())
+                        .collect::<Vec<String>>()
+                        .join(" ")
+                })
+                .collect();
+            lines.push(String::new());
+            lines
+        };
+        let draws: Vec<String> = (1..46).map(|n| n.to_string()).collect();
+        let mut lines = vec![draws.join(","), String::new()];
+        lines.extend(board((1..26).collect()));
+        lines.extend(board((1..26).collect()));
+        lines.extend(board((1..6).chain(26..46).collect()));
+        lines
+    }
+
+    #[test]
+    fn first_and_last_winners() {
+        let lines = read_data("test.txt");
+        let (numbers_drawn, boards) = get_input(&lines);
+        let win_draws = play_game(&numbers_drawn, &boards);
+        assert_eq!(get_first_last_winners(&win_draws), (Some(2), Some(1)));
+        assert_eq!(
+            get_first_last_winners(&[None, Some(3), Some(3), None]),
+            (Some(1), Some(2))
+        );
+        assert_eq!(get_first_last_winners(&[None, None]), (None, None));
+    }
+
+    #[test]
+    fn wilson_interval_bounds() {
+        assert_eq!(wilson_interval(0, 0), (0.0, 1.0));
+        let (low, high) = wilson_interval(0, 10);
+        assert!(low == 0.0 && high > 0.0 && high < 0.5);
+        let (low, high) = wilson_interval(50, 100);
+        assert!((low + high - 1.0).abs() < 1e-9 && low > 0.39 && high < 0.61);
+    }
+
+    #[test]
+    fn plans_on_the_sample() {
+        let lines = read_data("test.txt");
+        for target in 0..3 {
+            assert_plan_works(&lines, target, "first");
+            assert_plan_works(&lines, target, "last");
+        }
+    }
+
+    #[test]
+    fn shared_lines_block_plans() {
+        let lines = get_crafted_lines();
+        let (numbers_drawn, boards) = get_input(&lines);
+        let board_lines = get_board_lines(&numbers_drawn, &boards);
+        // Every line of board 1 is also board 0's, which wins the tie
+        assert_eq!(plan_first_win(1, &board_lines), Err(vec![0]));
+        // Whenever board 1 wins, board 0 has already won too
+        assert_eq!(plan_last_win(0, &board_lines), Err(vec![1]));
+        // Board 2 only loses its top row to the earlier boards
+        assert_plan_works(&lines, 2, "first");
+        assert_plan_works(&lines, 1, "last");
+        assert_plan_works(&lines, 2, "last");
+    }
+}
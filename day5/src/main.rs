@@ -1,15 +1,19 @@
 use clap::App;
 use clap::Arg;
-use std::cmp;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
 
-#[derive(Copy, Clone, Debug, Hash, Eq)]
+// 2D input leaves z at 0
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq, Ord, PartialOrd)]
 struct Point {
-    x: i32,
-    y: i32,
+    x: i64,
+    y: i64,
+    z: i64,
 }
 
+// Just x,y, for drawing and queries, which only take vents in the z = 0 plane
 impl fmt::Display for Point {
     fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
         fmt.write_fmt(format_args!("{},{}", self.x, self.y))
@@ -18,12 +22,46 @@ impl fmt::Display for Point {
     }
 }
 
-impl PartialEq for Point {
-    fn eq(&self, other: &Self) -> bool {
-        self.x == other.x && self.y == other.y
+impl Point {
+    // The way the input wrote points: with a depth only if it had depths at all
+    fn show(self, depth: bool) -> String {
+        if depth {
+            format!("{},{},{}", self.x, self.y, self.z)
+        } else {
+            self.to_string()
+        }
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SegmentKind {
+    Horizontal,
+    Vertical,
+    // Only the depth changes
+    Depth,
+    // Exactly 45° within one of the axis planes
+    Diagonal,
+    // The same distance along all three axes
+    SpaceDiagonal,
+    // Any other slope
+    Other,
+}
+
+const SEGMENT_KINDS: [SegmentKind; 6] = [
+    SegmentKind::Horizontal,
+    SegmentKind::Vertical,
+    SegmentKind::Depth,
+    SegmentKind::Diagonal,
+    SegmentKind::SpaceDiagonal,
+    SegmentKind::Other,
+];
+
+// Exact integer vector math for lines; i128 leaves room for products of billion-sized coordinates
+type Vector = (i128, i128, i128);
+
+// A line's step and which of the parallel lines with that step it is
+type LineKey = ((i64, i64, i64), Vector);
+
 #[derive(Debug)]
 struct Segment {
     p1: Point,
@@ -39,75 +77,181 @@ impl fmt::Display for Segment {
 }
 
 impl Segment {
-    fn is_vertical(&self) -> bool {
-        self.p1.x == self.p2.x
-    }
-    fn is_horizontal(&self) -> bool {
-        self.p1.y == self.p2.y
-    }
-    fn is_upper_diagonal(&self) -> bool {
-        let x_diff = self.p1.x - self.p2.x;
-        let y_diff = self.p1.y - self.p2.y;
-        x_diff == -y_diff
-    }
-    fn is_lower_diagonal(&self) -> bool {
-        let x_diff = self.p1.x - self.p2.x;
-        let y_diff = self.p1.y - self.p2.y;
-        x_diff == y_diff
-    }
-    fn get_left_right_points(&self) -> (Point, Point) {
-        let left_point;
-        let right_point;
-        if self.p1.x < self.p2.x {
-            left_point = self.p1;
-            right_point = self.p2;
-        } else {
-            left_point = self.p2;
-            right_point = self.p1;
+    fn show(&self, depth: bool) -> String {
+        format!("{} -> {}", self.p1.show(depth), self.p2.show(depth))
+    }
+    fn diff(&self) -> (i64, i64, i64) {
+        (
+            self.p2.x - self.p1.x,
+            self.p2.y - self.p1.y,
+            self.p2.z - self.p1.z,
+        )
+    }
+    fn kind(&self) -> SegmentKind {
+        let (x_diff, y_diff, z_diff) = self.diff();
+        let changing: Vec<i64> = [x_diff, y_diff, z_diff]
+            .iter()
+            .filter(|&&d| d != 0)
+            .map(|d| d.abs())
+            .collect();
+        let all_equal = changing.iter().all(|&d| d == changing[0]);
+        match changing.len() {
+            0 => SegmentKind::Horizontal,
+            1 if x_diff != 0 => SegmentKind::Horizontal,
+            1 if y_diff != 0 => SegmentKind::Vertical,
+            1 => SegmentKind::Depth,
+            2 if all_equal => SegmentKind::Diagonal,
+            3 if all_equal => SegmentKind::SpaceDiagonal,
+            _ => SegmentKind::Other,
         }
-        (left_point, right_point)
     }
-    fn points(&self, enable_diagonals: bool) -> Vec<Point> {
-        if self.is_vertical() {
-            return (cmp::min(self.p1.y, self.p2.y)..cmp::max(self.p1.y, self.p2.y) + 1)
-                .into_iter()
-                .map(|y| Point { x: self.p1.x, y: y })
-                .collect();
+    // Every lattice point on the segment, found by stepping from p1 to p2 by the smallest
+    // integer vector along it
+    fn points(&self) -> Vec<Point> {
+        let (x_diff, y_diff, z_diff) = self.diff();
+        let steps = gcd(gcd(x_diff, y_diff), z_diff);
+        if steps == 0 {
+            return vec![self.p1];
         }
-        if self.is_horizontal() {
-            return (cmp::min(self.p1.x, self.p2.x)..cmp::max(self.p1.x, self.p2.x) + 1)
-                .into_iter()
-                .map(|x| Point { x: x, y: self.p1.y })
-                .collect();
+        let (x_step, y_step, z_step) = (x_diff / steps, y_diff / steps, z_diff / steps);
+        (0..steps + 1)
+            .map(|i| Point {
+                x: self.p1.x + i * x_step,
+                y: self.p1.y + i * y_step,
+                z: self.p1.z + i * z_step,
+            })
+            .collect()
+    }
+    // The end to step from, the smallest integer step along the segment (its first non-zero
+    // coordinate positive) and how many of those steps reach the other end
+    fn ray(&self) -> (Point, (i64, i64, i64), i64) {
+        let (x_diff, y_diff, z_diff) = self.diff();
+        let steps = gcd(gcd(x_diff, y_diff), z_diff);
+        if steps == 0 {
+            return (self.p1, (1, 0, 0), 0);
         }
-        if enable_diagonals {
-            if self.is_lower_diagonal() {
-                let (left_point, right_point) = self.get_left_right_points();
-                let mut vec: Vec<Point> = Vec::new();
-                let mut y = left_point.y;
-                for x in left_point.x..right_point.x + 1 {
-                    vec.push(Point { x, y });
-                    y += 1;
+        let step = (x_diff / steps, y_diff / steps, z_diff / steps);
+        let sign = [step.0, step.1, step.2]
+            .iter()
+            .find(|&&d| d != 0)
+            .unwrap()
+            .signum();
+        if sign < 0 {
+            (self.p2, (-step.0, -step.1, -step.2), steps)
+        } else {
+            (self.p1, step, steps)
+        }
+    }
+    // Identifies the infinite line the segment lies on
+    fn line_key(&self) -> LineKey {
+        let (start, step, _) = self.ray();
+        (step, cross(to_vector(start), step_vector(step)))
+    }
+    // The first and last position the segment covers along its line
+    fn interval(&self) -> (i128, i128) {
+        let (_, step, _) = self.ray();
+        let p1_position = line_position(self.p1, step);
+        let p2_position = line_position(self.p2, step);
+        (p1_position.min(p2_position), p1_position.max(p2_position))
+    }
+    fn contains(&self, point: Point) -> bool {
+        let (start, step, _) = self.ray();
+        let (first, last) = self.interval();
+        let position = line_position(point, step);
+        cross(sub(to_vector(point), to_vector(start)), step_vector(step)) == (0, 0, 0)
+            && position >= first
+            && position <= last
+    }
+    // The part of the segment inside the box, if any
+    fn clip(&self, min: Point, max: Point) -> Option<Segment> {
+        let (start, (x_step, y_step, z_step), steps) = self.ray();
+        let (mut first, mut last) = (0, steps);
+        for (from, step, low, high) in [
+            (start.x, x_step, min.x, max.x),
+            (start.y, y_step, min.y, max.y),
+            (start.z, z_step, min.z, max.z),
+        ] {
+            // from + t·step has to land in low..=high
+            match step.signum() {
+                0 if from < low || from > high => return None,
+                1 => {
+                    first = first.max(-(from - low).div_euclid(step));
+                    last = last.min((high - from).div_euclid(step));
                 }
-                return vec;
-            }
-            if self.is_upper_diagonal() {
-                let (left_point, right_point) = self.get_left_right_points();
-                let mut vec: Vec<Point> = Vec::new();
-                let mut y = left_point.y;
-                for x in left_point.x..right_point.x + 1 {
-                    vec.push(Point { x, y });
-                    y -= 1;
+                -1 => {
+                    first = first.max(-(high - from).div_euclid(-step));
+                    last = last.min((from - low).div_euclid(-step));
                 }
-                return vec;
+                _ => (),
             }
         }
+        if first > last {
+            return None;
+        }
+        let at = |t: i64| Point {
+            x: start.x + t * x_step,
+            y: start.y + t * y_step,
+            z: start.z + t * z_step,
+        };
+        Some(Segment {
+            p1: at(first),
+            p2: at(last),
+        })
+    }
+    // The lattice point at a position along the segment's line
+    fn point_at(&self, position: i128) -> Point {
+        let (start, (x_step, y_step, z_step), _) = self.ray();
+        let steps = position - line_position(start, (x_step, y_step, z_step));
+        Point {
+            x: start.x + (steps * x_step as i128) as i64,
+            y: start.y + (steps * y_step as i128) as i64,
+            z: start.z + (steps * z_step as i128) as i64,
+        }
+    }
+}
 
-        return Vec::new();
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
     }
 }
 
-fn get_segments(lines: &Vec<String>) -> Vec<Segment> {
+fn to_vector(point: Point) -> Vector {
+    (point.x as i128, point.y as i128, point.z as i128)
+}
+
+fn step_vector(step: (i64, i64, i64)) -> Vector {
+    (step.0 as i128, step.1 as i128, step.2 as i128)
+}
+
+fn sub(a: Vector, b: Vector) -> Vector {
+    (a.0 - b.0, a.1 - b.1, a.2 - b.2)
+}
+
+fn dot(a: Vector, b: Vector) -> i128 {
+    a.0 * b.0 + a.1 * b.1 + a.2 * b.2
+}
+
+fn cross(a: Vector, b: Vector) -> Vector {
+    (
+        a.1 * b.2 - a.2 * b.1,
+        a.2 * b.0 - a.0 * b.2,
+        a.0 * b.1 - a.1 * b.0,
+    )
+}
+
+// Whether every segment lies in the z = 0 plane
+fn is_planar(segments: &[Segment]) -> bool {
+    segments
+        .iter()
+        .all(|segment| segment.p1.z == 0 && segment.p2.z == 0)
+}
+
+// The segments, and whether the input gave depths. A 2D point in 3D input is at depth 0.
+fn get_segments(lines: &[String]) -> (Vec<Segment>, bool) {
+    let mut depth = false;
     let mut points: Vec<Point> = Vec::new();
     let mut segments: Vec<Segment> = Vec::new();
     for line in lines {
@@ -117,10 +261,12 @@ fn get_segments(lines: &Vec<String>) -> Vec<Segment> {
                 .map(|l| l.trim())
                 .map(|l| {
                     let parts: Vec<&str> = l.split(",").map(|l| l.trim()).collect();
-                    return Point {
-                        x: parts[0].parse::<i32>().unwrap(),
-                        y: parts[1].parse::<i32>().unwrap(),
-                    };
+                    depth |= parts.len() > 2;
+                    Point {
+                        x: parts[0].parse::<i64>().unwrap(),
+                        y: parts[1].parse::<i64>().unwrap(),
+                        z: parts.get(2).map_or(0, |z| z.parse::<i64>().unwrap()),
+                    }
                 }),
         );
     }
@@ -132,50 +278,83 @@ fn get_segments(lines: &Vec<String>) -> Vec<Segment> {
             });
         }
     }
-    segments
+    (segments, depth)
 }
 
-fn get_max(segments: &Vec<Segment>) -> (i32, i32) {
-    let mut max_x = -1;
-    let mut max_y = -1;
+// The smallest box containing every segment
+fn get_bounds(segments: &[Segment]) -> (Point, Point) {
+    let mut min = Point {
+        x: i64::MAX,
+        y: i64::MAX,
+        z: i64::MAX,
+    };
+    let mut max = Point {
+        x: i64::MIN,
+        y: i64::MIN,
+        z: i64::MIN,
+    };
     for segment in segments {
-        if segment.p1.x > max_x {
-            max_x = segment.p1.x;
-        }
-        if segment.p2.x > max_x {
-            max_x = segment.p2.x;
-        }
-        if segment.p1.y > max_y {
-            max_y = segment.p1.y;
-        }
-        if segment.p2.y > max_y {
-            max_y = segment.p2.y;
+        for point in [segment.p1, segment.p2] {
+            min.x = min.x.min(point.x);
+            min.y = min.y.min(point.y);
+            min.z = min.z.min(point.z);
+            max.x = max.x.max(point.x);
+            max.y = max.y.max(point.y);
+            max.z = max.z.max(point.z);
         }
     }
 
-    (max_x, max_y)
+    (min, max)
 }
 
-fn print_board(segments: &Vec<Segment>) {
-    let (max_x, max_y) = get_max(&segments);
-    let mut point_map: HashMap<Point, i32> = HashMap::new();
-    println!("(0, 0) -> ({},{})", max_x, max_y);
-    for r in 0..max_x + 1 {
-        println!("x: {}", r);
-        for c in 0..max_y + 1 {
-            let point = Point { x: r, y: c };
-            for i in 0..segments.len() {
-                let entry = point_map.entry(point).or_insert(0);
-                let segment = &segments[i];
-                let segment_points = segment.points(true);
-                if segment_points.contains(&point) {
-                    *entry += 1
-                }
-            }
+// How many segments cover each point of a box, stored row by row
+struct CoverageGrid {
+    min: Point,
+    max: Point,
+    counts: Vec<u32>,
+}
+
+impl CoverageGrid {
+    fn width(&self) -> usize {
+        (self.max.x - self.min.x + 1) as usize
+    }
+    fn height(&self) -> usize {
+        (self.max.y - self.min.y + 1) as usize
+    }
+    fn get(&self, x: i64, y: i64) -> u32 {
+        self.counts[(y - self.min.y) as usize * self.width() + (x - self.min.x) as usize]
+    }
+    fn max_count(&self) -> u32 {
+        self.counts.iter().copied().max().unwrap_or(0)
+    }
+}
+
+// Rasterizes the part of every segment inside `min`..=`max`, once
+fn get_coverage_grid(segments: &[Segment], min: Point, max: Point) -> CoverageGrid {
+    let mut grid = CoverageGrid {
+        min,
+        max,
+        counts: Vec::new(),
+    };
+    grid.counts = vec![0; grid.width() * grid.height()];
+    let width = grid.width();
+    for segment in segments.iter().filter_map(|segment| segment.clip(min, max)) {
+        for point in segment.points() {
+            grid.counts[(point.y - min.y) as usize * width + (point.x - min.x) as usize] += 1;
+        }
+    }
+    grid
+}
+
+fn print_board(grid: &CoverageGrid) {
+    println!("({}) -> ({})", grid.min, grid.max);
+    for y in grid.min.y..grid.max.y + 1 {
+        for x in grid.min.x..grid.max.x + 1 {
+            let count = grid.get(x, y);
             print!(
                 "{}",
-                if point_map[&point] > 0 {
-                    point_map[&point].to_string()
+                if count > 0 {
+                    count.to_string()
                 } else {
                     ".".to_string()
                 }
@@ -184,55 +363,738 @@ fn print_board(segments: &Vec<Segment>) {
         println!();
     }
 
-    let mut max_intersections = 0;
-    let mut num_max_intersections = 0;
-    for point in point_map.keys() {
-        if point_map[point] >= max_intersections {
-            if point_map[point] > max_intersections {
-                max_intersections = point_map[point];
-                num_max_intersections = 0;
+    let max_intersections = grid.max_count();
+    let num_max_intersections = grid
+        .counts
+        .iter()
+        .filter(|&&count| count == max_intersections)
+        .count();
+    println!(
+        "{} point(s) covered by {} segments",
+        num_max_intersections, max_intersections
+    );
+}
+
+// Black for no vents, then blue, red and yellow up to white for the most overlaps
+fn get_ramp_color(count: u32, max_count: u32) -> [u8; 3] {
+    const RAMP: [[f64; 3]; 5] = [
+        [0.0, 0.0, 0.0],
+        [0.0, 0.0, 255.0],
+        [255.0, 0.0, 0.0],
+        [255.0, 255.0, 0.0],
+        [255.0, 255.0, 255.0],
+    ];
+    if count == 0 {
+        return [0, 0, 0];
+    }
+    // Any vent at all is at least the first step up from black
+    let t = if max_count <= 1 {
+        1.0
+    } else {
+        (count - 1) as f64 / (max_count - 1) as f64
+    };
+    let scaled = 1.0 + t * (RAMP.len() - 2) as f64;
+    let i = (scaled.floor() as usize).min(RAMP.len() - 2);
+    let frac = scaled - i as f64;
+    let mut color = [0u8; 3];
+    for c in 0..3 {
+        color[c] = (RAMP[i][c] + (RAMP[i + 1][c] - RAMP[i][c]) * frac).round() as u8;
+    }
+    color
+}
+
+// Writes a binary PGM (grayscale, for a .pgm path), or the color ramp as a binary PPM (.ppm) or
+// a PNG (.png)
+fn write_image(grid: &CoverageGrid, path: &str) -> io::Result<()> {
+    let max_count = grid.max_count();
+    let colors = || {
+        grid.counts
+            .iter()
+            .flat_map(|&count| get_ramp_color(count, max_count))
+    };
+    if path.ends_with(".pgm") {
+        let mut file = BufWriter::new(File::create(path)?);
+        write!(file, "P5\n{} {}\n255\n", grid.width(), grid.height())?;
+        for &count in &grid.counts {
+            file.write_all(&[(count as u64 * 255 / max_count.max(1) as u64) as u8])?;
+        }
+        file.flush()
+    } else if path.ends_with(".ppm") {
+        let mut file = BufWriter::new(File::create(path)?);
+        write!(file, "P6\n{} {}\n255\n", grid.width(), grid.height())?;
+        file.write_all(&colors().collect::<Vec<u8>>())?;
+        file.flush()
+    } else if path.ends_with(".png") {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, grid.width() as u32, grid.height() as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let to_io = |e: png::EncodingError| io::Error::other(e);
+        let mut writer = encoder.write_header().map_err(to_io)?;
+        writer
+            .write_image_data(&colors().collect::<Vec<u8>>())
+            .map_err(to_io)?;
+        writer.finish().map_err(to_io)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "expected a .pgm, .ppm or .png path",
+        ))
+    }
+}
+
+// Most cells a grid is allowed to have, a few GB at worst
+const MAX_GRID_CELLS: i128 = 1 << 28;
+
+// Bounds from `--crop x1,y1,x2,y2`, or the box around every segment
+fn get_image_bounds(segments: &[Segment], crop: Option<&str>) -> Result<(Point, Point), String> {
+    let (min, max) = match crop {
+        Some(crop) => {
+            let c: Vec<i64> = crop
+                .split(',')
+                .map(|n| n.trim().parse::<i64>())
+                .collect::<Result<Vec<i64>, _>>()
+                .map_err(|_| format!("Bad crop: {}", crop))?;
+            if c.len() != 4 {
+                return Err(format!("Bad crop: {} (needs X1,Y1,X2,Y2)", crop));
             }
-            num_max_intersections += 1;
+            (
+                Point {
+                    x: c[0].min(c[2]),
+                    y: c[1].min(c[3]),
+                    z: 0,
+                },
+                Point {
+                    x: c[0].max(c[2]),
+                    y: c[1].max(c[3]),
+                    z: 0,
+                },
+            )
         }
+        None if segments.is_empty() => return Err("No segments to draw".to_string()),
+        None => get_bounds(segments),
+    };
+    let cells = (max.x as i128 - min.x as i128 + 1) * (max.y as i128 - min.y as i128 + 1);
+    if cells > MAX_GRID_CELLS {
+        return Err(format!(
+            "{} -> {} is too big to draw, pick a smaller --crop",
+            min, max
+        ));
     }
+    Ok((min, max))
+}
 
-    println!("{}", num_max_intersections);
+fn print(lines: &[String], crop: Option<&str>) {
+    let (segments, _) = get_segments(lines);
+    if !is_planar(&segments) {
+        eprintln!("Only 2D vents can be drawn or queried");
+        return;
+    }
+    let (min, max) = match get_image_bounds(&segments, crop) {
+        Ok(bounds) => bounds,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    print_board(&get_coverage_grid(&segments, min, max));
 }
 
-fn p1(lines: &Vec<String>) {
-    let segments = get_segments(lines);
-    let mut point_map: HashMap<Point, i32> = HashMap::new();
-    for segment in segments {
-        for point in segment.points(false) {
-            let entry = point_map.entry(point).or_insert(0);
-            *entry += 1;
+fn image(lines: &[String], crop: Option<&str>, output: &str) {
+    let (segments, _) = get_segments(lines);
+    if !is_planar(&segments) {
+        eprintln!("Only 2D vents can be drawn or queried");
+        return;
+    }
+    let (min, max) = match get_image_bounds(&segments, crop) {
+        Ok(bounds) => bounds,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
         }
+    };
+    let grid = get_coverage_grid(&segments, min, max);
+    match write_image(&grid, output) {
+        Ok(()) => println!(
+            "Wrote {}x{} image to {} (max overlap {})",
+            grid.width(),
+            grid.height(),
+            output,
+            grid.max_count()
+        ),
+        Err(e) => eprintln!("Couldn't write {}: {}", output, e),
     }
-    let mut num_max_intersections = 0;
-    for point in point_map.keys() {
-        if point_map[point] >= 2 {
-            num_max_intersections += 1;
+}
+
+// Counts the points covered by at least two of the segments of the given kinds. Segments of any
+// other kind are listed on stderr, so nothing is dropped silently and stdout is just the answer.
+fn count_overlaps(lines: &[String], kinds: &[SegmentKind], method: &str) {
+    let (segments, depth) = get_segments(lines);
+    let (included, excluded): (Vec<&Segment>, Vec<&Segment>) = segments
+        .iter()
+        .partition(|segment| kinds.contains(&segment.kind()));
+    for kind in SEGMENT_KINDS {
+        let of_kind: Vec<&&Segment> = excluded.iter().filter(|s| s.kind() == kind).collect();
+        if !of_kind.is_empty() {
+            eprintln!("Ignoring {} {:?} segment(s)", of_kind.len(), kind);
+        }
+        // These used to be dropped without a word, so spell them out
+        if kind == SegmentKind::Other {
+            for segment in of_kind {
+                eprintln!("  {}", segment.show(depth));
+            }
         }
     }
-    println!("{}", num_max_intersections);
+
+    match method {
+        "lines" => println!("{}", count_overlaps_by_line(&included)),
+        _ => println!("{}", count_overlaps_map(&included)),
+    }
 }
 
-fn p2(lines: &Vec<String>) {
-    let segments = get_segments(lines);
+fn count_overlaps_map(segments: &[&Segment]) -> u64 {
     let mut point_map: HashMap<Point, i32> = HashMap::new();
     for segment in segments {
-        for point in segment.points(true) {
+        for point in segment.points() {
             let entry = point_map.entry(point).or_insert(0);
             *entry += 1;
         }
     }
-    let mut num_max_intersections = 0;
-    for point in point_map.keys() {
-        if point_map[point] >= 2 {
-            num_max_intersections += 1;
+    point_map.values().filter(|&&count| count >= 2).count() as u64
+}
+
+// Index of a lattice point along a line with the given step. Lattice points on one line all
+// leave the same remainder, so consecutive points get consecutive indices.
+fn line_position(point: Point, step: (i64, i64, i64)) -> i128 {
+    let step = step_vector(step);
+    dot(to_vector(point), step).div_euclid(dot(step, step))
+}
+
+// Number of positions covered by at least `times` of the (inclusive) intervals
+fn count_covered(intervals: &[(i128, i128)], times: i32) -> i128 {
+    let mut events: Vec<(i128, i32)> = Vec::new();
+    for &(start, end) in intervals {
+        events.push((start, 1));
+        events.push((end + 1, -1));
+    }
+    events.sort_unstable();
+
+    let mut covered = 0;
+    let mut coverage = 0;
+    let mut previous = 0;
+    for (position, change) in events {
+        if coverage >= times {
+            covered += position - previous;
         }
+        coverage += change;
+        previous = position;
     }
-    println!("{}", num_max_intersections);
+    covered
+}
+
+// The lattice point where two non-parallel segments cross, if they do
+fn get_crossing(a: &Segment, b: &Segment) -> Option<Point> {
+    let (a_start, a_step, a_steps) = a.ray();
+    let (b_start, b_step, b_steps) = b.ray();
+    let (u, v) = (step_vector(a_step), step_vector(b_step));
+    let normal = cross(u, v);
+    if normal == (0, 0, 0) {
+        return None;
+    }
+    let w = sub(to_vector(b_start), to_vector(a_start));
+    // Lines that don't share a plane never meet
+    if dot(w, normal) != 0 {
+        return None;
+    }
+    // a_start + t·u = b_start + s·v gives t·(u×v) = w×v and s·(u×v) = w×u. Any non-zero
+    // component of u×v will do, and both have to land on a whole step within the segment.
+    let (t_cross, s_cross) = (cross(w, v), cross(w, u));
+    let (t_numerator, s_numerator, denominator) = if normal.2 != 0 {
+        (t_cross.2, s_cross.2, normal.2)
+    } else if normal.1 != 0 {
+        (t_cross.1, s_cross.1, normal.1)
+    } else {
+        (t_cross.0, s_cross.0, normal.0)
+    };
+    if t_numerator % denominator != 0 || s_numerator % denominator != 0 {
+        return None;
+    }
+    let (t, s) = (t_numerator / denominator, s_numerator / denominator);
+    if t < 0 || t > a_steps as i128 || s < 0 || s > b_steps as i128 {
+        return None;
+    }
+    Some(a.point_at(line_position(a_start, a_step) + t))
+}
+
+// Calls `visit` with each pair of segments whose x ranges overlap, the only ones that can meet,
+// lower index first. Segments are taken left to right, and each one is only paired with those
+// still overlapping it in x. Pairs aren't kept, so memory stays linear.
+fn for_each_candidate_pair(segments: &[&Segment], mut visit: impl FnMut(usize, usize)) {
+    let min_x = |i: usize| segments[i].p1.x.min(segments[i].p2.x);
+    let max_x = |i: usize| segments[i].p1.x.max(segments[i].p2.x);
+    let mut order: Vec<usize> = (0..segments.len()).collect();
+    order.sort_by_key(|&i| min_x(i));
+
+    let mut active: Vec<usize> = Vec::new();
+    for i in order {
+        active.retain(|&j| max_x(j) >= min_x(i));
+        for &j in &active {
+            visit(i.min(j), i.max(j));
+        }
+        active.push(i);
+    }
+}
+
+// Every crossing point of segments on different lines, with the segments through it
+fn get_crossings(segments: &[&Segment]) -> HashMap<Point, HashSet<usize>> {
+    let mut crossings: HashMap<Point, HashSet<usize>> = HashMap::new();
+    for_each_candidate_pair(segments, |i, j| {
+        if let Some(point) = get_crossing(segments[i], segments[j]) {
+            let covering = crossings.entry(point).or_default();
+            covering.insert(i);
+            covering.insert(j);
+        }
+    });
+    crossings
+}
+
+// Segments grouped by the line they lie on
+fn get_lines(segments: &[&Segment]) -> HashMap<LineKey, Vec<usize>> {
+    let mut lines: HashMap<LineKey, Vec<usize>> = HashMap::new();
+    for (i, segment) in segments.iter().enumerate() {
+        lines.entry(segment.line_key()).or_default().push(i);
+    }
+    lines
+}
+
+// Counts points covered at least twice without visiting every point. Overlaps of segments on
+// the same line are interval sweeps along that line; overlaps between lines can only be at their
+// crossing points, found by checking segments pairwise (only those overlapping in x, but that
+// can still be every pair).
+fn count_overlaps_by_line(segments: &[&Segment]) -> u64 {
+    let mut count: i128 = get_lines(segments)
+        .values()
+        .map(|line| {
+            let intervals: Vec<(i128, i128)> =
+                line.iter().map(|&i| segments[i].interval()).collect();
+            count_covered(&intervals, 2)
+        })
+        .sum();
+
+    // A crossing point is covered at least twice no matter what. It may already have been
+    // counted by some of its lines, so take those back out and count it once.
+    for covering in get_crossings(segments).values() {
+        let mut per_line: HashMap<LineKey, usize> = HashMap::new();
+        for &i in covering {
+            *per_line.entry(segments[i].line_key()).or_insert(0) += 1;
+        }
+        count -= per_line.values().filter(|&&n| n >= 2).count() as i128;
+        count += 1;
+    }
+    count as u64
+}
+
+enum Meeting {
+    Crossing(Point),
+    // The shared stretch of two segments on the same line
+    Overlap(Point, Point),
+}
+
+// How two segments meet, if they share any lattice point
+fn get_meeting(a: &Segment, b: &Segment) -> Option<Meeting> {
+    if a.line_key() != b.line_key() {
+        return get_crossing(a, b).map(Meeting::Crossing);
+    }
+    let (a_start, a_end) = a.interval();
+    let (b_start, b_end) = b.interval();
+    let (start, end) = (a_start.max(b_start), a_end.min(b_end));
+    if start > end {
+        return None;
+    }
+    if start == end {
+        return Some(Meeting::Crossing(a.point_at(start)));
+    }
+    Some(Meeting::Overlap(a.point_at(start), a.point_at(end)))
+}
+
+// The highest coverage along a line, how many positions have it and the first one that does
+fn get_coverage_peak(intervals: &[(i128, i128)]) -> (usize, i128, i128) {
+    let mut events: Vec<(i128, i32)> = Vec::new();
+    for &(start, end) in intervals {
+        events.push((start, 1));
+        events.push((end + 1, -1));
+    }
+    events.sort_unstable();
+
+    let (mut peak, mut peak_positions, mut peak_start) = (0usize, 0i128, 0i128);
+    let mut coverage = 0i32;
+    let mut previous = 0i128;
+    for (position, change) in events {
+        if position > previous && coverage > 0 {
+            let coverage = coverage as usize;
+            if coverage > peak {
+                peak = coverage;
+                peak_positions = 0;
+                peak_start = previous;
+            }
+            if coverage == peak {
+                peak_positions += position - previous;
+            }
+        }
+        coverage += change;
+        previous = position;
+    }
+    (peak, peak_positions, peak_start)
+}
+
+// The most segments covering any one point, how many points are covered that much and one of
+// them (the smallest example found, so it doesn't depend on hashing order). Crossing points get
+// their full count; everywhere else only one line contributes.
+fn get_hottest(segments: &[&Segment]) -> (usize, i128, Point) {
+    let crossings = get_crossings(segments);
+    let mut hottest = (0usize, 0i128, Point { x: 0, y: 0, z: 0 });
+    for line in get_lines(segments).values() {
+        let intervals: Vec<(i128, i128)> = line.iter().map(|&i| segments[i].interval()).collect();
+        let (peak, positions, start) = get_coverage_peak(&intervals);
+        let point = segments[line[0]].point_at(start);
+        if peak > hottest.0 {
+            hottest = (peak, 0, point);
+        }
+        if peak == hottest.0 {
+            hottest.1 += positions;
+            hottest.2 = hottest.2.min(point);
+        }
+    }
+
+    // A crossing is covered by more segments than any one line through it, so no line can have
+    // counted it among the hottest points already
+    for (&point, covering) in &crossings {
+        if covering.len() > hottest.0 {
+            hottest = (covering.len(), 0, point);
+        }
+        if covering.len() == hottest.0 {
+            hottest.1 += 1;
+            hottest.2 = hottest.2.min(point);
+        }
+    }
+    hottest
+}
+
+fn meetings(lines: &[String]) {
+    let (segments, depth) = get_segments(lines);
+    let segments: Vec<&Segment> = segments.iter().collect();
+    let mut found: Vec<(usize, usize, Meeting)> = Vec::new();
+    for_each_candidate_pair(&segments, |i, j| {
+        if let Some(meeting) = get_meeting(segments[i], segments[j]) {
+            found.push((i, j, meeting));
+        }
+    });
+    found.sort_unstable_by_key(|&(i, j, _)| (i, j));
+
+    let num_meetings = found.len();
+    for (i, j, meeting) in found {
+        let pair = format!(
+            "#{} {} and #{} {}",
+            i,
+            segments[i].show(depth),
+            j,
+            segments[j].show(depth)
+        );
+        match meeting {
+            Meeting::Crossing(point) => println!("{} cross at {}", pair, point.show(depth)),
+            Meeting::Overlap(start, end) => println!(
+                "{} overlap from {} to {}",
+                pair,
+                start.show(depth),
+                end.show(depth)
+            ),
+        }
+    }
+    println!("{} pair(s) meet", num_meetings);
+
+    if segments.is_empty() {
+        return;
+    }
+    let (coverage, num_points, point) = get_hottest(&segments);
+    let covering: Vec<String> = segments
+        .iter()
+        .enumerate()
+        .filter(|(_, segment)| segment.contains(point))
+        .map(|(i, _)| format!("#{}", i))
+        .collect();
+    println!(
+        "Hottest: {} point(s) covered by {} segments, e.g. {} ({})",
+        num_points,
+        coverage,
+        point.show(depth),
+        covering.join(", ")
+    );
+}
+
+// Uniform buckets over the (2D) vent field, each listing the segments with a point inside it.
+// Buckets are sized so there are at most a few hundred along either axis, whatever the
+// coordinates.
+struct SegmentIndex<'a> {
+    segments: &'a [Segment],
+    // The box around every segment
+    min: Point,
+    max: Point,
+    bucket_size: i64,
+    buckets: HashMap<(i64, i64), Vec<usize>>,
+}
+
+impl<'a> SegmentIndex<'a> {
+    fn new(segments: &'a [Segment]) -> SegmentIndex<'a> {
+        const BUCKETS_PER_AXIS: i64 = 256;
+        let (min, max) = get_bounds(segments);
+        let extent = if segments.is_empty() {
+            1
+        } else {
+            (max.x - min.x).max(max.y - min.y) + 1
+        };
+        let bucket_size = (extent + BUCKETS_PER_AXIS - 1) / BUCKETS_PER_AXIS;
+        let mut index = SegmentIndex {
+            segments,
+            min,
+            max,
+            bucket_size,
+            buckets: HashMap::new(),
+        };
+        for (i, segment) in segments.iter().enumerate() {
+            let (low, high) = get_bounds(std::slice::from_ref(segment));
+            let (low_x, low_y) = index.bucket_of(low);
+            let (high_x, high_y) = index.bucket_of(high);
+            for bucket_x in low_x..high_x + 1 {
+                for bucket_y in low_y..high_y + 1 {
+                    let (bucket_min, bucket_max) = index.bucket_bounds((bucket_x, bucket_y));
+                    if segment.clip(bucket_min, bucket_max).is_some() {
+                        index
+                            .buckets
+                            .entry((bucket_x, bucket_y))
+                            .or_default()
+                            .push(i);
+                    }
+                }
+            }
+        }
+        index
+    }
+    fn bucket_of(&self, point: Point) -> (i64, i64) {
+        (
+            point.x.div_euclid(self.bucket_size),
+            point.y.div_euclid(self.bucket_size),
+        )
+    }
+    fn bucket_bounds(&self, bucket: (i64, i64)) -> (Point, Point) {
+        let min = Point {
+            x: bucket.0 * self.bucket_size,
+            y: bucket.1 * self.bucket_size,
+            z: 0,
+        };
+        let max = Point {
+            x: min.x + self.bucket_size - 1,
+            y: min.y + self.bucket_size - 1,
+            z: 0,
+        };
+        (min, max)
+    }
+    // Segments that cover the point
+    fn covering(&self, point: Point) -> Vec<usize> {
+        match self.buckets.get(&self.bucket_of(point)) {
+            Some(bucket) => bucket
+                .iter()
+                .copied()
+                .filter(|&i| self.segments[i].contains(point))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+    // Segments with at least one point in the box. Only the buckets the box overlaps are looked
+    // at, and there are no buckets outside the segments' own box.
+    fn in_box(&self, min: Point, max: Point) -> Vec<usize> {
+        let mut found: HashSet<usize> = HashSet::new();
+        if self.segments.is_empty() {
+            return Vec::new();
+        }
+        let (first_x, first_y) = self.bucket_of(self.min);
+        let (last_x, last_y) = self.bucket_of(self.max);
+        let (low_x, low_y) = self.bucket_of(min);
+        let (high_x, high_y) = self.bucket_of(max);
+        for bucket_x in low_x.max(first_x)..high_x.min(last_x) + 1 {
+            for bucket_y in low_y.max(first_y)..high_y.min(last_y) + 1 {
+                if let Some(bucket) = self.buckets.get(&(bucket_x, bucket_y)) {
+                    found.extend(
+                        bucket
+                            .iter()
+                            .filter(|&&i| self.segments[i].clip(min, max).is_some()),
+                    );
+                }
+            }
+        }
+        let mut found: Vec<usize> = found.into_iter().collect();
+        found.sort_unstable();
+        found
+    }
+    // Distinct points in the box covered by any vent. Each line's stretches are merged, and a
+    // crossing point shared by several lines is only counted once.
+    fn count_vent_points(&self, min: Point, max: Point) -> u64 {
+        let clipped: Vec<Segment> = self
+            .in_box(min, max)
+            .into_iter()
+            .filter_map(|i| self.segments[i].clip(min, max))
+            .collect();
+        let clipped: Vec<&Segment> = clipped.iter().collect();
+        let mut count: i128 = get_lines(&clipped)
+            .values()
+            .map(|line| {
+                let intervals: Vec<(i128, i128)> =
+                    line.iter().map(|&i| clipped[i].interval()).collect();
+                count_covered(&intervals, 1)
+            })
+            .sum();
+        for covering in get_crossings(&clipped).values() {
+            let lines: HashSet<LineKey> = covering.iter().map(|&i| clipped[i].line_key()).collect();
+            count -= lines.len() as i128 - 1;
+        }
+        count as u64
+    }
+    // The closest point (by steps on the grid) covered by fewer than two vents. Stepping left out
+    // of the segments' box is always safe, so the search never has to go further than that.
+    fn nearest_safe(&self, point: Point) -> (Point, i64) {
+        let is_safe = |p: Point| self.covering(p).len() < 2;
+        if self.segments.is_empty() || point.x < self.min.x {
+            return (point, 0);
+        }
+        let limit = point.x - self.min.x + 1;
+        for distance in 0..limit {
+            for dx in -distance..distance + 1 {
+                let dy = distance - dx.abs();
+                for y in [point.y - dy, point.y + dy] {
+                    let candidate = Point {
+                        x: point.x + dx,
+                        y,
+                        ..point
+                    };
+                    if is_safe(candidate) {
+                        return (candidate, distance);
+                    }
+                }
+            }
+        }
+        (
+            Point {
+                x: point.x - limit,
+                ..point
+            },
+            limit,
+        )
+    }
+}
+
+fn parse_point(text: &str) -> Result<Point, String> {
+    let parts: Vec<i64> = text
+        .split(',')
+        .map(|n| n.trim().parse::<i64>())
+        .collect::<Result<Vec<i64>, _>>()
+        .map_err(|_| format!("expected X,Y, got {}", text))?;
+    match parts.as_slice() {
+        &[x, y] => Ok(Point { x, y, z: 0 }),
+        _ => Err(format!("expected X,Y, got {}", text)),
+    }
+}
+
+// The answer to one query line, or nothing for a blank line
+fn answer_query(index: &SegmentIndex, line: &str) -> Result<Option<String>, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let answer = match words.as_slice() {
+        [] => return Ok(None),
+        ["at", point] => {
+            let point = parse_point(point)?;
+            let covering: Vec<String> = index
+                .covering(point)
+                .iter()
+                .map(|&i| format!("#{} {}", i, index.segments[i]))
+                .collect();
+            format!("at {}: {} [{}]", point, covering.len(), covering.join(", "))
+        }
+        ["count", corner1, corner2] => {
+            let (corner1, corner2) = (parse_point(corner1)?, parse_point(corner2)?);
+            let min = Point {
+                x: corner1.x.min(corner2.x),
+                y: corner1.y.min(corner2.y),
+                z: 0,
+            };
+            let max = Point {
+                x: corner1.x.max(corner2.x),
+                y: corner1.y.max(corner2.y),
+                z: 0,
+            };
+            format!(
+                "count {} {}: {} segment(s), {} vent point(s)",
+                min,
+                max,
+                index.in_box(min, max).len(),
+                index.count_vent_points(min, max)
+            )
+        }
+        ["nearest", point] => {
+            let point = parse_point(point)?;
+            let (safe, distance) = index.nearest_safe(point);
+            format!("nearest {}: {} ({} away)", point, safe, distance)
+        }
+        _ => return Err("unknown query".to_string()),
+    };
+    Ok(Some(answer))
+}
+
+// Answers one query per line of the file:
+//   at X,Y                which segments cover the point
+//   count X1,Y1 X2,Y2     how many segments and vent points are in the box
+//   nearest X,Y           the closest point covered by fewer than two vents
+fn query(lines: &[String], queries_file: &str) {
+    let (segments, _) = get_segments(lines);
+    if !is_planar(&segments) {
+        eprintln!("Only 2D vents can be drawn or queried");
+        return;
+    }
+    let index = SegmentIndex::new(&segments);
+    for line in common::read_file(queries_file) {
+        match answer_query(&index, &line) {
+            Ok(Some(answer)) => println!("{}", answer),
+            Ok(None) => (),
+            Err(e) => eprintln!("Bad query: {} ({})", line, e),
+        }
+    }
+}
+
+fn p1(lines: &[String], method: &str) {
+    count_overlaps(
+        lines,
+        &[
+            SegmentKind::Horizontal,
+            SegmentKind::Vertical,
+            SegmentKind::Depth,
+        ],
+        method,
+    );
+}
+
+fn p2(lines: &[String], method: &str) {
+    count_overlaps(
+        lines,
+        &[
+            SegmentKind::Horizontal,
+            SegmentKind::Vertical,
+            SegmentKind::Depth,
+            SegmentKind::Diagonal,
+            SegmentKind::SpaceDiagonal,
+        ],
+        method,
+    );
+}
+
+fn p3(lines: &[String], method: &str) {
+    count_overlaps(lines, &SEGMENT_KINDS, method);
 }
 
 fn main() {
@@ -245,19 +1107,111 @@ fn main() {
                 .short("p")
                 .long("part")
                 .value_name("PART")
-                .help("Which part"),
+                .help("Which part (1: axis-aligned, 2: +45° diagonals, 3: every slope, print, image, meetings, query)"),
+        )
+        .arg(
+            Arg::with_name("method")
+                .short("m")
+                .long("method")
+                .value_name("METHOD")
+                .default_value("map")
+                .possible_values(&["map", "lines"])
+                .help("Count overlaps point by point, or line by line without visiting points"),
+        )
+        .arg(
+            Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("FILE")
+                .default_value("/Users/alexeyler/Development/aoc/2021/day5/src/data/vents.txt")
+                .help("Vent segments, one x1,y1 -> x2,y2 per line"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("FILE")
+                .default_value("vents.ppm")
+                .help("Image to write, .pgm for grayscale or .ppm or .png for color (image)"),
+        )
+        .arg(
+            Arg::with_name("crop")
+                .long("crop")
+                .value_name("X1,Y1,X2,Y2")
+                .help("Only draw this box (print, image)"),
+        )
+        .arg(
+            Arg::with_name("queries")
+                .short("q")
+                .long("queries")
+                .value_name("FILE")
+                .help("File with one query per line: at X,Y / count X1,Y1 X2,Y2 / nearest X,Y"),
         )
         .get_matches();
 
     let part = matches
         .value_of("part")
         .expect("Part (-p, --part) required.");
+    let method = matches.value_of("method").unwrap();
+    let output = matches.value_of("output").unwrap();
+    let crop = matches.value_of("crop");
 
-    let lines: Vec<String> =
-        common::read_file("/Users/alexeyler/Development/aoc/2021/day5/src/data/vents.txt");
+    let lines: Vec<String> = common::read_file(matches.value_of("input").unwrap());
     match part {
-        "1" => p1(&lines),
-        "2" => p2(&lines),
+        "1" => p1(&lines, method),
+        "2" => p2(&lines, method),
+        "3" => p3(&lines, method),
+        "print" => print(&lines, crop),
+        "image" => image(&lines, crop, output),
+        "meetings" => meetings(&lines),
+        "query" => query(
+            &lines,
+            matches
+                .value_of("queries")
+                .expect("Queries (-q, --queries) required."),
+        ),
         _ => eprintln!("Bad arg value: {}", part),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_data(name: &str) -> Vec<String> {
+        common::read_file(&format!("{}/src/data/{}", env!("CARGO_MANIFEST_DIR"), name))
+    }
+
+    // Both counts over the segments of the given kinds
+    fn count_both_ways(lines: &[String], kinds: &[SegmentKind]) -> (u64, u64) {
+        let (segments, _) = get_segments(lines);
+        let included: Vec<&Segment> = segments
+            .iter()
+            .filter(|segment| kinds.contains(&segment.kind()))
+            .collect();
+        (
+            count_overlaps_map(&included),
+            count_overlaps_by_line(&included),
+        )
+    }
+
+    const AXIS_KINDS: [SegmentKind; 3] = [
+        SegmentKind::Horizontal,
+        SegmentKind::Vertical,
+        SegmentKind::Depth,
+    ];
+
+    #[test]
+    fn by_line_matches_map_on_test() {
+        let lines = read_data("test.txt");
+        assert_eq!(count_both_ways(&lines, &AXIS_KINDS), (5, 5));
+        assert_eq!(count_both_ways(&lines, &SEGMENT_KINDS), (12, 12));
+    }
+
+    #[test]
+    fn by_line_matches_map_on_vents() {
+        let lines = read_data("vents.txt");
+        assert_eq!(count_both_ways(&lines, &AXIS_KINDS), (5167, 5167));
+        assert_eq!(count_both_ways(&lines, &SEGMENT_KINDS), (17604, 17604));
+    }
+}
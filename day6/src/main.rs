@@ -1,18 +1,78 @@
 use clap::App;
 use clap::Arg;
+use num_bigint::BigUint;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::{Beta, Binomial};
 use rayon::prelude::*;
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
 use std::sync::{Arc, Mutex};
 
 const TIMER_RESTART: i32 = 6;
 const TIMER_NEW: i32 = 8;
+// Part 1 keeps every fish in memory, so stop before the population eats it all
+const MAX_FISH: usize = 1 << 28;
+
+// How many fish have each timer value after a day, given how many had each value the day before
+type Matrix<C> = [[C; 9]; 9];
+
+// Fish counts are either u64s, which refuse to overflow, or arbitrary-precision integers
+trait FishCount: Clone + fmt::Display {
+    fn from_u64(n: u64) -> Self;
+    fn checked_add(&self, other: &Self) -> Option<Self>;
+    // self + a·b, reduced by the modulus if there is one
+    fn mul_add(&self, a: &Self, b: &Self, modulus: Option<u64>) -> Option<Self>;
+    fn to_biguint(&self) -> BigUint;
+}
+
+impl FishCount for u64 {
+    fn from_u64(n: u64) -> Self {
+        n
+    }
+    fn checked_add(&self, other: &Self) -> Option<Self> {
+        u64::checked_add(*self, *other)
+    }
+    fn mul_add(&self, a: &Self, b: &Self, modulus: Option<u64>) -> Option<Self> {
+        let sum = *self as u128 + *a as u128 * *b as u128;
+        match modulus {
+            Some(m) => Some((sum % m as u128) as u64),
+            None => u64::try_from(sum).ok(),
+        }
+    }
+    fn to_biguint(&self) -> BigUint {
+        BigUint::from(*self)
+    }
+}
+
+impl FishCount for BigUint {
+    fn from_u64(n: u64) -> Self {
+        BigUint::from(n)
+    }
+    fn checked_add(&self, other: &Self) -> Option<Self> {
+        Some(self + other)
+    }
+    fn mul_add(&self, a: &Self, b: &Self, modulus: Option<u64>) -> Option<Self> {
+        let sum = self + a * b;
+        Some(match modulus {
+            Some(m) => sum % m,
+            None => sum,
+        })
+    }
+    fn to_biguint(&self) -> BigUint {
+        self.clone()
+    }
+}
 
 struct Lanternfish {
-    timer: i32,
+    timer: u8,
 }
 
-fn print_fish_timers(day: i32, fishes: &Vec<Lanternfish>) {
-    let timers: Vec<i32> = fishes.iter().map(|f| f.timer).collect();
+fn print_fish_timers(day: u64, fishes: &[Lanternfish]) {
+    let timers: Vec<u8> = fishes.iter().map(|f| f.timer).collect();
     if day == 0 {
         println!("Initial state: {:?}", timers)
     } else {
@@ -20,7 +80,7 @@ fn print_fish_timers(day: i32, fishes: &Vec<Lanternfish>) {
     }
 }
 
-fn print_map(day: i32, map: &HashMap<i32, u64>) {
+fn print_map<C: FishCount>(day: u64, map: &HashMap<i32, C>) {
     let mut sorted: Vec<_> = map.iter().collect();
     sorted.sort_by_key(|k| k.0);
 
@@ -31,20 +91,45 @@ fn print_map(day: i32, map: &HashMap<i32, u64>) {
     println!();
 }
 
-fn get_sum(map: &HashMap<i32, u64>) -> u64 {
-    let mut sum: u64 = 0;
+fn get_sum<C: FishCount>(map: &HashMap<i32, C>) -> Option<C> {
+    let mut sum = C::from_u64(0);
     for i in 0..9 {
-        sum += map[&i];
+        sum = sum.checked_add(&map[&i])?;
     }
-    sum
+    Some(sum)
 }
 
-fn p1(lines: &Vec<String>, days: i32, debug: bool) {
-    let initial_state_line = lines.get(0).unwrap();
+// Groups digits in threes so huge counts can be read at a glance
+fn format_digits(count: &BigUint) -> String {
+    let digits = count.to_string();
+    let mut grouped = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    format!("{} ({} digits)", grouped, digits.len())
+}
+
+fn print_count<C: FishCount>(count: &C, modulus: Option<u64>, bigint: bool) {
+    let count = if bigint {
+        format_digits(&count.to_biguint())
+    } else {
+        count.to_string()
+    };
+    match modulus {
+        Some(m) => println!("Fishies: {} (mod {})", count, m),
+        None => println!("Fishies: {}", count),
+    }
+}
+
+fn p1(lines: &[String], days: u64, debug: bool) {
+    let initial_state_line = lines.first().unwrap();
     let mut fishes: Vec<Lanternfish> = initial_state_line
         .split(',')
         .map(|t| Lanternfish {
-            timer: t.parse::<i32>().unwrap(),
+            timer: t.parse::<u8>().unwrap(),
         })
         .collect();
     if debug {
@@ -54,14 +139,24 @@ fn p1(lines: &Vec<String>, days: i32, debug: bool) {
         println!("Day {}", day);
         let new_fishes = Arc::new(Mutex::new(0));
         fishes.par_iter_mut().for_each(|fish| {
-            fish.timer -= 1;
-            if fish.timer == -1 {
-                fish.timer = TIMER_RESTART;
+            if fish.timer == 0 {
+                fish.timer = TIMER_RESTART as u8;
                 *new_fishes.lock().unwrap() += 1;
+            } else {
+                fish.timer -= 1;
             }
         });
-        for _ in 0..*new_fishes.lock().unwrap() {
-            fishes.push(Lanternfish { timer: TIMER_NEW })
+        let new_fishes = *new_fishes.lock().unwrap();
+        if fishes.len() + new_fishes > MAX_FISH {
+            panic!(
+                "More than {} fish on day {}; use part 2 or 3 instead",
+                MAX_FISH, day
+            );
+        }
+        for _ in 0..new_fishes {
+            fishes.push(Lanternfish {
+                timer: TIMER_NEW as u8,
+            })
         }
         if debug {
             print_fish_timers(day, &fishes);
@@ -71,36 +166,880 @@ fn p1(lines: &Vec<String>, days: i32, debug: bool) {
     println!("{} fishies", fishes.len());
 }
 
-fn p2(lines: &Vec<String>, days: i32) {
-    let initial_state_line = lines.get(0).unwrap();
-    let mut map: HashMap<i32, u64> = HashMap::new();
+// The fish count after `days`, or the day it overflowed
+fn count_p2<C: FishCount>(lines: &[String], days: u64, debug: bool) -> Result<C, u64> {
+    let initial_state_line = lines.first().unwrap();
+    let mut map: HashMap<i32, C> = HashMap::new();
     for i in 0..9 {
-        map.insert(i, 0);
+        map.insert(i, C::from_u64(0));
     }
     let initial_timers: Vec<i32> = initial_state_line
         .split(',')
         .map(|t| t.parse::<i32>().unwrap())
         .collect();
     for timer in initial_timers {
-        let entry = map.entry(timer).or_insert(0);
-        *entry += 1;
+        let entry = map.entry(timer).or_insert_with(|| C::from_u64(0));
+        *entry = entry.checked_add(&C::from_u64(1)).unwrap();
     }
     for day in 1..days + 1 {
-        let expiring_timers = map[&0];
+        let expiring_timers = map[&0].clone();
         for i in 1..9 {
             let prev_i = i - 1;
-            map.insert(prev_i, map[&i]);
+            map.insert(prev_i, map[&i].clone());
         }
-        map.insert(6, expiring_timers + map[&6]);
+        let restarted = expiring_timers.checked_add(&map[&6]).ok_or(day)?;
+        map.insert(6, restarted);
         map.insert(8, expiring_timers);
+        if debug {
+            print_map(day, &map);
+        }
     }
 
-    let mut sum: u64 = 0;
+    get_sum(&map).ok_or(days)
+}
+
+fn p2<C: FishCount>(lines: &[String], days: u64, debug: bool, bigint: bool) {
+    let sum = count_p2::<C>(lines, days, debug)
+        .unwrap_or_else(|day| panic!("Fish count overflowed u64 on day {}; try --bigint", day));
+    print_count(&sum, None, bigint);
+}
+
+fn get_transition_matrix<C: FishCount>() -> Matrix<C> {
+    std::array::from_fn(|new_timer| {
+        std::array::from_fn(|old_timer| {
+            let mut entry = (old_timer == new_timer + 1) as u64;
+            if old_timer == 0 {
+                entry += (new_timer == TIMER_RESTART as usize) as u64;
+                entry += (new_timer == TIMER_NEW as usize) as u64;
+            }
+            C::from_u64(entry)
+        })
+    })
+}
+
+fn multiply<C: FishCount>(a: &Matrix<C>, b: &Matrix<C>, modulus: Option<u64>) -> Option<Matrix<C>> {
+    let mut product: Matrix<C> = std::array::from_fn(|_| std::array::from_fn(|_| C::from_u64(0)));
     for i in 0..9 {
-        sum += map[&i];
+        for j in 0..9 {
+            for k in 0..9 {
+                product[i][j] = product[i][j].mul_add(&a[i][k], &b[k][j], modulus)?;
+            }
+        }
+    }
+    Some(product)
+}
+
+// Raises the matrix to `exponent` by repeated squaring
+fn power<C: FishCount>(
+    matrix: &Matrix<C>,
+    mut exponent: u64,
+    modulus: Option<u64>,
+) -> Option<Matrix<C>> {
+    let mut result: Matrix<C> =
+        std::array::from_fn(|i| std::array::from_fn(|j| C::from_u64((i == j) as u64)));
+    let mut base = matrix.clone();
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = multiply(&result, &base, modulus)?;
+        }
+        exponent >>= 1;
+        // The last squaring isn't needed, and could overflow when the answer doesn't
+        if exponent > 0 {
+            base = multiply(&base, &base, modulus)?;
+        }
+    }
+    Some(result)
+}
+
+// How many fish start with each timer value
+fn get_initial_counts(lines: &[String]) -> [u64; 9] {
+    let initial_state_line = lines.first().unwrap();
+    let mut counts = [0u64; 9];
+    for timer in initial_state_line.split(',') {
+        counts[timer.parse::<usize>().unwrap()] += 1;
+    }
+    counts
+}
+
+// Same model as p2, but advancing the 9 timer buckets with a matrix power, so it takes
+// O(log days) steps. With a modulus every count is kept reduced, which gives exact answers
+// modulo M for any number of days.
+fn count_p3<C: FishCount>(lines: &[String], days: u64, modulus: Option<u64>) -> Option<C> {
+    let counts = get_initial_counts(lines);
+    let matrix = power(&get_transition_matrix::<C>(), days, modulus)?;
+    let mut sum = C::from_u64(0);
+    for row in matrix.iter() {
+        for (entry, &count) in row.iter().zip(counts.iter()) {
+            sum = sum.mul_add(entry, &C::from_u64(count), modulus)?;
+        }
+    }
+    Some(sum)
+}
+
+fn p3<C: FishCount>(lines: &[String], days: u64, modulus: Option<u64>, bigint: bool) {
+    let sum = count_p3::<C>(lines, days, modulus)
+        .expect("Fish count overflowed u64; try --bigint or --modulus");
+    print_count(&sum, modulus, bigint);
+}
+
+// One kind of fish in a species config. Timers mean the same as in the puzzle input: days left
+// before the next spawn, counting down to 0.
+struct Species {
+    name: String,
+    // Days between spawns for an adult
+    cycle: usize,
+    // Days before a newborn spawns for the first time
+    newborn: usize,
+    // Newborns per spawn
+    offspring: f64,
+    // Fish older than this (in days) die
+    max_age: Option<u64>,
+    // Fraction of fish that die each day
+    mortality: f64,
+    // Starting timers; the input file's when not given
+    initial: Option<Vec<usize>>,
+}
+
+// The puzzle's lanternfish
+fn get_default_species() -> Species {
+    Species {
+        name: String::from("lanternfish"),
+        cycle: TIMER_RESTART as usize + 1,
+        newborn: TIMER_NEW as usize + 1,
+        offspring: 1.0,
+        max_age: None,
+        mortality: 0.0,
+        initial: None,
+    }
+}
+
+// One species per line: a name followed by key=value settings, e.g.
+//   lanternfish cycle=7 newborn=9 offspring=1
+//   grouper cycle=5 newborn=8 offspring=2 max_age=60 mortality=0.01 initial=1,4,2
+// Anything left out is the lanternfish default. Blank lines and lines starting with # are skipped.
+fn get_species(lines: &[String]) -> Result<Vec<Species>, String> {
+    let mut all_species: Vec<Species> = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let bad = |what: String| format!("Bad species on line {}: {}", i + 1, what);
+        let mut words = line.split_whitespace();
+        let mut species = get_default_species();
+        species.name = String::from(words.next().unwrap());
+        for setting in words {
+            let (key, value) = setting
+                .split_once('=')
+                .ok_or_else(|| bad(format!("expected key=value, got {}", setting)))?;
+            let bad_value = || bad(format!("{} can't be {}", key, value));
+            match key {
+                "cycle" => species.cycle = value.parse::<usize>().map_err(|_| bad_value())?,
+                "newborn" => species.newborn = value.parse::<usize>().map_err(|_| bad_value())?,
+                "offspring" => species.offspring = value.parse::<f64>().map_err(|_| bad_value())?,
+                "max_age" => species.max_age = Some(value.parse::<u64>().map_err(|_| bad_value())?),
+                "mortality" => species.mortality = value.parse::<f64>().map_err(|_| bad_value())?,
+                "initial" => {
+                    species.initial = Some(
+                        value
+                            .split(',')
+                            .map(|t| t.parse::<usize>())
+                            .collect::<Result<Vec<usize>, _>>()
+                            .map_err(|_| bad_value())?,
+                    )
+                }
+                _ => return Err(bad(format!("unknown setting {} for {}", key, species.name))),
+            }
+        }
+        if species.cycle == 0 || species.newborn == 0 {
+            return Err(bad(format!(
+                "{} needs a cycle and newborn delay of at least 1",
+                species.name
+            )));
+        }
+        if !(species.offspring >= 0.0 && species.offspring.is_finite()) {
+            return Err(bad(format!(
+                "{} can't have {} offspring",
+                species.name, species.offspring
+            )));
+        }
+        if !(0.0..=1.0).contains(&species.mortality) {
+            return Err(bad(format!(
+                "{} needs a mortality between 0 and 1, not {}",
+                species.name, species.mortality
+            )));
+        }
+        all_species.push(species);
     }
+    Ok(all_species)
+}
+
+// Expected fish of one species, bucketed by (timer, age) like p2 buckets by timer. Ages are only
+// tracked when the species has a max age; otherwise they all stay 0. Expected counts are
+// fractional as soon as anything dies, so they're f64s, exact only up to 2^53.
+struct Population {
+    buckets: HashMap<(usize, u64), f64>,
+}
+
+impl Population {
+    // Starting fish are assumed to be in their first cycle, so their age follows from the timer
+    fn new(species: &Species, timers: &[usize]) -> Population {
+        let mut buckets: HashMap<(usize, u64), f64> = HashMap::new();
+        for &timer in timers {
+            let age = match species.max_age {
+                Some(_) => species.newborn.saturating_sub(timer + 1) as u64,
+                None => 0,
+            };
+            *buckets.entry((timer, age)).or_insert(0.0) += 1.0;
+        }
+        Population { buckets }
+    }
+    fn total(&self) -> f64 {
+        self.buckets.values().sum()
+    }
+    // Fish by timer alone, summed over ages
+    fn timers(&self, species: &Species) -> Vec<f64> {
+        let mut timers = vec![0.0; species.cycle.max(species.newborn)];
+        for (&(timer, _), &count) in &self.buckets {
+            timers[timer] += count;
+        }
+        timers
+    }
+    fn scale(&mut self, factor: f64) {
+        for count in self.buckets.values_mut() {
+            *count *= factor;
+        }
+    }
+    fn advance(&mut self, species: &Species) {
+        let mut next: HashMap<(usize, u64), f64> = HashMap::new();
+        let mut newborns = 0.0;
+        for (&(timer, age), &count) in &self.buckets {
+            let survivors = count * (1.0 - species.mortality);
+            let age = match species.max_age {
+                Some(max_age) if age + 1 > max_age => continue,
+                Some(_) => age + 1,
+                None => 0,
+            };
+            let timer = if timer == 0 {
+                newborns += survivors * species.offspring;
+                species.cycle - 1
+            } else {
+                timer - 1
+            };
+            *next.entry((timer, age)).or_insert(0.0) += survivors;
+        }
+        if newborns > 0.0 {
+            *next.entry((species.newborn - 1, 0)).or_insert(0.0) += newborns;
+        }
+        self.buckets = next;
+    }
+}
+
+// Runs every species in the config side by side. Without a config it's just the puzzle's
+// lanternfish, the same model as part 2, though totals past 2^53 get rounded.
+fn run_species(lines: &[String], config: Option<&str>, days: u64, debug: bool) {
+    let all_species = match config {
+        Some(config) => match get_species(&common::read_file(config)) {
+            Ok(all_species) => all_species,
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        },
+        None => vec![get_default_species()],
+    };
+    let mut populations = get_populations(lines, &all_species);
 
-    println!("Fishies: {}", sum);
+    for day in 1..days + 1 {
+        for (population, species) in populations.iter_mut().zip(all_species.iter()) {
+            population.advance(species);
+        }
+        if debug {
+            let totals: Vec<String> = populations
+                .iter()
+                .zip(all_species.iter())
+                .map(|(population, species)| format!("{}: {:.0}", species.name, population.total()))
+                .collect();
+            println!("Day {}: {}", day, totals.join(", "));
+        }
+    }
+
+    let mut total = 0.0;
+    for (population, species) in populations.iter().zip(all_species.iter()) {
+        println!("{}: {:.0}", species.name, population.total());
+        total += population.total();
+    }
+    println!("Fishies: {:.0}", total);
+}
+
+// Each species' starting population, from its own timers or the input file's
+fn get_populations(lines: &[String], all_species: &[Species]) -> Vec<Population> {
+    let input_timers: Vec<usize> = lines
+        .first()
+        .unwrap()
+        .split(',')
+        .map(|t| t.parse::<usize>().unwrap())
+        .collect();
+    all_species
+        .iter()
+        .map(|species| Population::new(species, species.initial.as_ref().unwrap_or(&input_timers)))
+        .collect()
+}
+
+// Every day's timer buckets, starting with day 0
+fn get_time_series(counts: &[u64; 9], days: u64) -> Vec<[BigUint; 9]> {
+    let matrix = get_transition_matrix::<BigUint>();
+    let mut series: Vec<[BigUint; 9]> = vec![std::array::from_fn(|i| BigUint::from(counts[i]))];
+    for _ in 0..days {
+        let tomorrow = apply(&matrix, series.last().unwrap());
+        series.push(tomorrow);
+    }
+    series
+}
+
+fn write_csv(series: &[[BigUint; 9]], path: &str) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "day,total")?;
+    for timer in 0..9 {
+        write!(file, ",timer{}", timer)?;
+    }
+    writeln!(file)?;
+    for (day, counts) in series.iter().enumerate() {
+        let total: BigUint = counts.iter().sum();
+        write!(file, "{},{}", day, total)?;
+        for count in counts.iter() {
+            write!(file, ",{}", count)?;
+        }
+        writeln!(file)?;
+    }
+    file.flush()
+}
+
+// The transition matrix's largest eigenvalue, found by power iteration. The matrix is primitive
+// (spawn cycles of 7 and 9 days share no factor), so any population ends up growing by this
+// factor every day.
+fn get_growth_rate() -> f64 {
+    let matrix = get_transition_matrix::<u64>();
+    let mut vector = [1.0f64; 9];
+    let mut rate = 1.0;
+    for _ in 0..10000 {
+        let next: [f64; 9] =
+            std::array::from_fn(|i| (0..9).map(|j| matrix[i][j] as f64 * vector[j]).sum::<f64>());
+        let next_rate = next.iter().sum::<f64>() / vector.iter().sum::<f64>();
+        vector = next.map(|v| v / next_rate);
+        if (next_rate - rate).abs() < 1e-15 {
+            return next_rate;
+        }
+        rate = next_rate;
+    }
+    rate
+}
+
+fn get_total(counts: &[BigUint; 9]) -> BigUint {
+    counts.iter().sum()
+}
+
+// The buckets after however many days `matrix` advances
+fn apply(matrix: &Matrix<BigUint>, counts: &[BigUint; 9]) -> [BigUint; 9] {
+    std::array::from_fn(|i| {
+        let mut count = BigUint::from(0u64);
+        for (entry, old) in matrix[i].iter().zip(counts.iter()) {
+            count = count.mul_add(entry, old, None).unwrap();
+        }
+        count
+    })
+}
+
+// The population never shrinks, so gallop out past the target by squaring the transition
+// matrix, then walk back down the same powers of two to the last day still at or under it.
+// Gives up once a million years of days still haven't gotten there.
+fn get_first_day_exceeding(counts: &[u64; 9], target: &BigUint) -> Option<u64> {
+    const MAX_DAYS: u64 = 365_000_000;
+    let start: [BigUint; 9] = std::array::from_fn(|i| BigUint::from(counts[i]));
+    if get_total(&start) > *target {
+        return Some(0);
+    }
+    // Nothing to grow
+    if counts.iter().all(|&count| count == 0) {
+        return None;
+    }
+    // powers[k] advances 2^k days
+    let mut powers: Vec<Matrix<BigUint>> = vec![get_transition_matrix()];
+    while get_total(&apply(powers.last().unwrap(), &start)) <= *target {
+        if 1u64 << powers.len() > MAX_DAYS {
+            return None;
+        }
+        let last = powers.last().unwrap();
+        powers.push(multiply(last, last, None).unwrap());
+    }
+    // Past the target within 2^k days, so the last day at or under it is below that
+    let mut day = 0;
+    let mut today = start;
+    for (k, matrix) in powers.iter().enumerate().rev() {
+        let later = apply(matrix, &today);
+        if get_total(&later) <= *target {
+            today = later;
+            day += 1 << k;
+        }
+    }
+    Some(day + 1)
+}
+
+// Long-run daily growth factor of one species, by power iteration on its own population. The
+// population is scaled back to one fish every day, and the log growth of the last stretch is
+// averaged so the answer settles even for populations that pulse.
+fn get_species_growth_rate(species: &Species) -> f64 {
+    const DAYS: usize = 20_000;
+    const AVERAGED: usize = 5_000;
+    let timers: Vec<usize> = (0..species.cycle.max(species.newborn)).collect();
+    let mut population = Population::new(species, &timers);
+    let mut log_growth = 0.0;
+    for day in 0..DAYS {
+        let total = population.total();
+        if total <= 0.0 {
+            return 0.0;
+        }
+        population.scale(1.0 / total);
+        population.advance(species);
+        if day >= DAYS - AVERAGED {
+            log_growth += population.total().ln();
+        }
+    }
+    (log_growth / AVERAGED as f64).exp()
+}
+
+fn print_growth_rate(rate: f64) {
+    println!("Growth rate: {:.6} per day", rate);
+    if rate > 1.0 {
+        println!("Doubling time: {:.3} days", 2f64.ln() / rate.ln());
+    } else if rate > 0.0 && rate < 1.0 {
+        println!("Halving time: {:.3} days", 2f64.ln() / -rate.ln());
+    }
+}
+
+// Growth of the species in a config, from their (expected, f64) populations. Unlike the puzzle's
+// fish they can die out, so the target is only looked for within `days`.
+fn growth_species(
+    lines: &[String],
+    all_species: &[Species],
+    days: u64,
+    csv: Option<&str>,
+    exceeds: Option<&str>,
+    debug: bool,
+) {
+    let mut populations = get_populations(lines, all_species);
+    // Each day's timer buckets for every species, starting with day 0
+    let mut buckets: Vec<Vec<Vec<f64>>> = Vec::new();
+    for day in 0..days + 1 {
+        if day > 0 {
+            for (population, species) in populations.iter_mut().zip(all_species.iter()) {
+                population.advance(species);
+            }
+        }
+        buckets.push(
+            populations
+                .iter()
+                .zip(all_species.iter())
+                .map(|(population, species)| population.timers(species))
+                .collect(),
+        );
+    }
+    let series: Vec<Vec<f64>> = buckets
+        .iter()
+        .map(|day| day.iter().map(|timers| timers.iter().sum()).collect())
+        .collect();
+    let names: Vec<&str> = all_species.iter().map(|s| s.name.as_str()).collect();
+    if debug {
+        for (day, totals) in series.iter().enumerate() {
+            let totals: Vec<String> = names
+                .iter()
+                .zip(totals.iter())
+                .map(|(name, total)| format!("{}: {:.0}", name, total))
+                .collect();
+            println!("Day {}: {}", day, totals.join(", "));
+        }
+    }
+    if let Some(path) = csv {
+        let write = || -> io::Result<()> {
+            let mut file = BufWriter::new(File::create(path)?);
+            write!(file, "day,total,{}", names.join(","))?;
+            for (name, timers) in names.iter().zip(buckets[0].iter()) {
+                for timer in 0..timers.len() {
+                    write!(file, ",{}_timer{}", name, timer)?;
+                }
+            }
+            writeln!(file)?;
+            for (day, (totals, timers)) in series.iter().zip(buckets.iter()).enumerate() {
+                let columns: Vec<String> = totals
+                    .iter()
+                    .chain(timers.iter().flatten())
+                    .map(|t| format!("{:.3}", t))
+                    .collect();
+                let total: f64 = totals.iter().sum();
+                writeln!(file, "{},{:.3},{}", day, total, columns.join(","))?;
+            }
+            file.flush()
+        };
+        match write() {
+            Ok(()) => println!("Wrote {} days to {}", series.len(), path),
+            Err(e) => eprintln!("Couldn't write {}: {}", path, e),
+        }
+    }
+
+    for species in all_species {
+        println!("{}:", species.name);
+        print_growth_rate(get_species_growth_rate(species));
+    }
+
+    if let Some(exceeds) = exceeds {
+        let target = match exceeds.parse::<f64>() {
+            Ok(target) => target,
+            Err(_) => {
+                eprintln!("Bad arg value: {}", exceeds);
+                return;
+            }
+        };
+        match series
+            .iter()
+            .position(|totals| totals.iter().sum::<f64>() > target)
+        {
+            Some(day) => println!("Population first exceeds {} on day {}", target, day),
+            None => println!("Population doesn't exceed {} within {} days", target, days),
+        }
+    }
+}
+
+fn growth(
+    lines: &[String],
+    days: u64,
+    config: Option<&str>,
+    csv: Option<&str>,
+    exceeds: Option<&str>,
+    debug: bool,
+) {
+    if let Some(config) = config {
+        let all_species = match get_species(&common::read_file(config)) {
+            Ok(all_species) => all_species,
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        };
+        growth_species(lines, &all_species, days, csv, exceeds, debug);
+        return;
+    }
+    let counts = get_initial_counts(lines);
+    if csv.is_some() || debug {
+        let series = get_time_series(&counts, days);
+        if debug {
+            for (day, counts) in series.iter().enumerate() {
+                let total: BigUint = counts.iter().sum();
+                let counts: Vec<String> = counts.iter().map(|c| c.to_string()).collect();
+                println!("Day {}: {} [{}]", day, total, counts.join(", "));
+            }
+        }
+        if let Some(path) = csv {
+            match write_csv(&series, path) {
+                Ok(()) => println!("Wrote {} days to {}", series.len(), path),
+                Err(e) => eprintln!("Couldn't write {}: {}", path, e),
+            }
+        }
+    }
+
+    print_growth_rate(get_growth_rate());
+
+    if let Some(exceeds) = exceeds {
+        let target = match exceeds.parse::<BigUint>() {
+            Ok(target) => target,
+            Err(_) => {
+                eprintln!("Bad arg value: {}", exceeds);
+                return;
+            }
+        };
+        match get_first_day_exceeding(&counts, &target) {
+            Some(day) => println!("Population first exceeds {} on day {}", target, day),
+            None => println!(
+                "Population doesn't exceed {} within a million years",
+                target
+            ),
+        }
+    }
+}
+
+// How many days something takes, as (days, probability) pairs. Written as `7`, `uniform:6-8`,
+// `normal:7,1` or `poisson:7`; normal and poisson are cut off at 1 day and far out in the tail.
+struct Distribution {
+    outcomes: Vec<(usize, f64)>,
+}
+
+impl Distribution {
+    fn parse(s: &str) -> Result<Distribution, String> {
+        let (kind, params) = s.split_once(':').unwrap_or(("fixed", s));
+        let params: Vec<f64> = params
+            .split([',', '-'])
+            .map(|p| p.trim().parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .ok()
+            .filter(|params| params.iter().all(|p| p.is_finite()))
+            .ok_or_else(|| format!("Bad distribution: {}", s))?;
+        let weights: Vec<(usize, f64)> = match (kind, params.as_slice()) {
+            ("fixed", &[days]) if days >= 1.0 => vec![(days as usize, 1.0)],
+            ("uniform", &[low, high]) if low.min(high) >= 1.0 => (low.min(high) as usize
+                ..low.max(high) as usize + 1)
+                .map(|days| (days, 1.0))
+                .collect(),
+            ("normal", &[mean, sd]) if mean > 0.0 && sd > 0.0 => {
+                (1..(mean + 6.0 * sd).ceil() as usize + 1)
+                    .map(|days| (days, (-0.5 * ((days as f64 - mean) / sd).powi(2)).exp()))
+                    .collect()
+            }
+            ("poisson", &[mean]) if mean > 0.0 => {
+                let mut weight = (-mean).exp();
+                let mut weights = Vec::new();
+                for days in 1..(mean + 10.0 * mean.sqrt()).ceil() as usize + 10 {
+                    weight *= mean / days as f64;
+                    weights.push((days, weight));
+                }
+                weights
+            }
+            ("fixed", _) | ("uniform", _) | ("normal", _) | ("poisson", _) => {
+                return Err(format!(
+                    "Bad distribution: {} (days are at least 1, mean and SD above 0)",
+                    s
+                ))
+            }
+            _ => return Err(format!("Bad distribution: {}", s)),
+        };
+        let total: f64 = weights.iter().map(|w| w.1).sum();
+        if !(total > 0.0 && total.is_finite()) {
+            return Err(format!(
+                "Bad distribution: {} (nothing left after a day)",
+                s
+            ));
+        }
+        Ok(Distribution {
+            outcomes: weights.iter().map(|&(d, w)| (d, w / total)).collect(),
+        })
+    }
+    fn max_days(&self) -> usize {
+        self.outcomes.iter().map(|o| o.0).max().unwrap()
+    }
+    // Splits `count` fish between the outcomes, i.e. a multinomial draw done as a chain of
+    // binomials, so it costs the same for a billion fish as for one
+    fn split<R: Rng>(&self, count: u64, rng: &mut R) -> Vec<(usize, u64)> {
+        let mut remaining = count;
+        let mut mass = 1.0;
+        let mut split = Vec::new();
+        for &(days, probability) in &self.outcomes {
+            if remaining == 0 {
+                break;
+            }
+            let p = (probability / mass).min(1.0);
+            let n = if p >= 1.0 {
+                remaining
+            } else {
+                rng.sample(Binomial::new(remaining, p).unwrap())
+            };
+            split.push((days, n));
+            remaining -= n;
+            mass -= probability;
+        }
+        if remaining > 0 {
+            split.push((self.outcomes.last().unwrap().0, remaining));
+        }
+        split
+    }
+}
+
+// The chance each fish survives a day, drawn afresh each day so good and bad days come and go.
+// Written as `P`, `uniform:A-B` or `beta:A,B`.
+enum Survival {
+    Fixed(f64),
+    Uniform(f64, f64),
+    Beta(Beta<f64>),
+}
+
+impl Survival {
+    fn parse(s: &str) -> Result<Survival, String> {
+        let (kind, params) = s.split_once(':').unwrap_or(("fixed", s));
+        let params: Vec<f64> = params
+            .split([',', '-'])
+            .map(|p| p.trim().parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|_| format!("Bad survival: {}", s))?;
+        let is_probability = |p: f64| (0.0..=1.0).contains(&p);
+        match (kind, params.as_slice()) {
+            ("fixed", &[p]) if is_probability(p) => Ok(Survival::Fixed(p)),
+            ("uniform", &[low, high]) if is_probability(low) && is_probability(high) => {
+                Ok(Survival::Uniform(low.min(high), low.max(high)))
+            }
+            ("beta", &[a, b]) => Beta::new(a, b)
+                .map(Survival::Beta)
+                .map_err(|_| format!("Bad survival: {} (needs A, B > 0)", s)),
+            _ => Err(format!(
+                "Bad survival: {} (probabilities are between 0 and 1)",
+                s
+            )),
+        }
+    }
+    fn sample<R: Rng>(&self, rng: &mut R) -> f64 {
+        match self {
+            Survival::Fixed(p) => *p,
+            Survival::Uniform(low, high) if low == high => *low,
+            Survival::Uniform(low, high) => rng.gen_range(*low..=*high),
+            Survival::Beta(beta) => rng.sample(beta),
+        }
+    }
+}
+
+// One run of the stochastic model, returning the population on each day (day 0 included).
+// Each day every fish survives with that day's chance drawn from `survival`; spawning fish draw
+// how long until they spawn again from `interval`, and their young draw their first wait from
+// `newborn`.
+fn simulate_stochastic<R: Rng>(
+    counts: &[u64; 9],
+    days: u64,
+    interval: &Distribution,
+    newborn: &Distribution,
+    survival: &Survival,
+    rng: &mut R,
+) -> Vec<u64> {
+    // timers[t] is how many fish spawn in t days
+    let mut timers = vec![0u64; interval.max_days().max(newborn.max_days()).max(9)];
+    timers[..9].copy_from_slice(counts);
+    let mut totals = vec![timers.iter().sum()];
+    for day in 1..days + 1 {
+        let p = survival.sample(rng);
+        if p < 1.0 {
+            for count in timers.iter_mut().filter(|c| **c > 0) {
+                *count = rng.sample(Binomial::new(*count, p).unwrap());
+            }
+        }
+        let spawning = timers[0];
+        timers.rotate_left(1);
+        *timers.last_mut().unwrap() = 0;
+        for (wait, n) in interval
+            .split(spawning, rng)
+            .into_iter()
+            .chain(newborn.split(spawning, rng))
+        {
+            timers[wait - 1] += n;
+        }
+        let total = timers
+            .iter()
+            .try_fold(0u64, |sum, &c| sum.checked_add(c))
+            .unwrap_or_else(|| panic!("Fish count overflowed u64 on day {}", day));
+        totals.push(total);
+    }
+    totals
+}
+
+// Nearest-rank percentile of sorted values
+fn get_percentile(sorted: &[u64], percentile: f64) -> u64 {
+    let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+struct StochasticOptions<'a> {
+    trials: u64,
+    seed: u64,
+    interval: &'a str,
+    newborn: &'a str,
+    survival: &'a str,
+    percentiles: Vec<f64>,
+    csv: Option<&'a str>,
+}
+
+fn stochastic(lines: &[String], days: u64, options: &StochasticOptions) {
+    if options.trials == 0 {
+        eprintln!("Bad arg value: need at least one trial");
+        return;
+    }
+    let survival = match Survival::parse(options.survival) {
+        Ok(survival) => survival,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    let (interval, newborn) = match (
+        Distribution::parse(options.interval),
+        Distribution::parse(options.newborn),
+    ) {
+        (Ok(interval), Ok(newborn)) => (interval, newborn),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    let counts = get_initial_counts(lines);
+    let runs: Vec<Vec<u64>> = (0..options.trials)
+        .into_par_iter()
+        .map(|trial| {
+            let mut rng = StdRng::seed_from_u64(common::get_trial_seed(options.seed, trial));
+            simulate_stochastic(&counts, days, &interval, &newborn, &survival, &mut rng)
+        })
+        .collect();
+
+    // One row per day: the mean, then each percentile
+    let curves: Vec<(f64, Vec<u64>)> = (0..days as usize + 1)
+        .map(|day| {
+            let mut totals: Vec<u64> = runs.iter().map(|run| run[day]).collect();
+            totals.sort_unstable();
+            let mean = totals.iter().map(|&t| t as f64).sum::<f64>() / totals.len() as f64;
+            let percentiles = options
+                .percentiles
+                .iter()
+                .map(|&p| get_percentile(&totals, p))
+                .collect();
+            (mean, percentiles)
+        })
+        .collect();
+
+    let header: Vec<String> = options
+        .percentiles
+        .iter()
+        .map(|p| format!("p{}", p))
+        .collect();
+    match options.csv {
+        Some(path) => {
+            let write = || -> io::Result<()> {
+                let mut file = BufWriter::new(File::create(path)?);
+                writeln!(file, "day,mean,{}", header.join(","))?;
+                for (day, (mean, percentiles)) in curves.iter().enumerate() {
+                    let percentiles: Vec<String> =
+                        percentiles.iter().map(|p| p.to_string()).collect();
+                    writeln!(file, "{},{:.2},{}", day, mean, percentiles.join(","))?;
+                }
+                file.flush()
+            };
+            match write() {
+                Ok(()) => println!("Wrote {} days to {}", curves.len(), path),
+                Err(e) => eprintln!("Couldn't write {}: {}", path, e),
+            }
+        }
+        None => {
+            println!("day\tmean\t{}", header.join("\t"));
+            for (day, (mean, percentiles)) in curves.iter().enumerate() {
+                let percentiles: Vec<String> = percentiles.iter().map(|p| p.to_string()).collect();
+                println!("{}\t{:.2}\t{}", day, mean, percentiles.join("\t"));
+            }
+        }
+    }
+
+    let (mean, percentiles) = curves.last().unwrap();
+    let spread: Vec<String> = header
+        .iter()
+        .zip(percentiles.iter())
+        .map(|(p, count)| format!("{} {}", p, count))
+        .collect();
+    println!(
+        "{} trials (seed {}): mean {:.2} fishies on day {}, {}",
+        options.trials,
+        options.seed,
+        mean,
+        days,
+        spread.join(", ")
+    );
 }
 
 fn main() {
@@ -113,7 +1052,7 @@ fn main() {
                 .short("p")
                 .long("part")
                 .value_name("PART")
-                .help("Which part")
+                .help("Which part (1, 2, 3: matrix power, species, growth, stochastic)")
                 .required(true),
         )
         .arg(
@@ -131,16 +1070,250 @@ fn main() {
                 .takes_value(false)
                 .help("Enable debug mode"),
         )
+        .arg(
+            Arg::with_name("modulus")
+                .short("m")
+                .long("modulus")
+                .value_name("M")
+                .help("Count fish modulo M (part 3)"),
+        )
+        .arg(
+            Arg::with_name("bigint")
+                .long("bigint")
+                .takes_value(false)
+                .help("Count with arbitrary precision instead of u64 (parts 2 and 3)"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .value_name("FILE")
+                .help("Species definitions, one per line (species, growth)"),
+        )
+        .arg(
+            Arg::with_name("csv")
+                .long("csv")
+                .value_name("FILE")
+                .help("Write each day's counts as CSV (growth, stochastic)"),
+        )
+        .arg(
+            Arg::with_name("exceeds")
+                .long("exceeds")
+                .value_name("N")
+                .help("Find the first day the population is over N (growth; with --config, only within --days)"),
+        )
+        .arg(
+            Arg::with_name("trials")
+                .short("t")
+                .long("trials")
+                .value_name("TRIALS")
+                .default_value("1000")
+                .help("# of simulated populations (stochastic)"),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .short("s")
+                .long("seed")
+                .value_name("SEED")
+                .default_value("0")
+                .help("RNG seed (stochastic)"),
+        )
+        .arg(
+            Arg::with_name("interval")
+                .long("interval")
+                .value_name("DIST")
+                .default_value("7")
+                .help("Days between spawns: N, uniform:A-B, normal:MEAN,SD or poisson:MEAN (stochastic)"),
+        )
+        .arg(
+            Arg::with_name("newborn")
+                .long("newborn")
+                .value_name("DIST")
+                .default_value("9")
+                .help("Days before a newborn first spawns, same forms as --interval (stochastic)"),
+        )
+        .arg(
+            Arg::with_name("survival")
+                .long("survival")
+                .value_name("P")
+                .default_value("1")
+                .help("Chance each fish survives a day, drawn daily: P, uniform:A-B or beta:A,B (stochastic)"),
+        )
+        .arg(
+            Arg::with_name("percentiles")
+                .long("percentiles")
+                .value_name("LIST")
+                .default_value("5,50,95")
+                .help("Comma-separated percentiles to report (stochastic)"),
+        )
         .get_matches();
 
     let part = matches.value_of("part").unwrap();
-    let days = matches.value_of("days").unwrap().parse::<i32>().unwrap();
+    let days = matches.value_of("days").unwrap().parse::<u64>().unwrap();
     let debug = matches.is_present("debug");
+    let bigint = matches.is_present("bigint");
+    // Counts are reduced mod M, so M has to be at least 1
+    let modulus = match matches.value_of("modulus").map(|m| m.parse::<u64>()) {
+        None => None,
+        Some(Ok(m)) if m > 0 => Some(m),
+        Some(_) => {
+            eprintln!("Bad arg value: modulus must be a positive integer");
+            return;
+        }
+    };
     let lines: Vec<String> =
         common::read_file("/Users/alexeyler/Development/aoc/2021/day6/src/data/fish.txt");
     match part {
         "1" => p1(&lines, days, debug),
-        "2" => p2(&lines, days),
+        "2" if bigint => p2::<BigUint>(&lines, days, debug, bigint),
+        "2" => p2::<u64>(&lines, days, debug, bigint),
+        "3" if bigint => p3::<BigUint>(&lines, days, modulus, bigint),
+        "3" => p3::<u64>(&lines, days, modulus, bigint),
+        "species" => run_species(&lines, matches.value_of("config"), days, debug),
+        "growth" => growth(
+            &lines,
+            days,
+            matches.value_of("config"),
+            matches.value_of("csv"),
+            matches.value_of("exceeds"),
+            debug,
+        ),
+        "stochastic" => stochastic(
+            &lines,
+            days,
+            &StochasticOptions {
+                trials: matches.value_of("trials").unwrap().parse::<u64>().unwrap(),
+                seed: matches.value_of("seed").unwrap().parse::<u64>().unwrap(),
+                interval: matches.value_of("interval").unwrap(),
+                newborn: matches.value_of("newborn").unwrap(),
+                survival: matches.value_of("survival").unwrap(),
+                percentiles: matches
+                    .value_of("percentiles")
+                    .unwrap()
+                    .split(',')
+                    .map(|p| p.parse::<f64>().unwrap())
+                    .collect(),
+                csv: matches.value_of("csv"),
+            },
+        ),
         _ => eprintln!("Bad arg value: {}", part),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_data(name: &str) -> Vec<String> {
+        common::read_file(&format!("{}/src/data/{}", env!("CARGO_MANIFEST_DIR"), name))
+    }
+
+    #[test]
+    fn matrix_power_matches_day_by_day() {
+        let lines = read_data("test.txt");
+        for (days, expected) in [(18, 26), (80, 5934), (256, 26_984_457_539)] {
+            assert_eq!(count_p2::<u64>(&lines, days, false), Ok(expected));
+            assert_eq!(count_p3::<u64>(&lines, days, None), Some(expected));
+            assert_eq!(
+                count_p3::<BigUint>(&lines, days, None),
+                Some(BigUint::from(expected))
+            );
+        }
+        // Reduced counts are exact even long after u64 gives out
+        let modulus = 1_000_000_007;
+        for days in [256, 1000, 5000] {
+            let exact = count_p2::<BigUint>(&lines, days, false).unwrap();
+            let reduced = exact % modulus;
+            assert_eq!(
+                count_p3::<u64>(&lines, days, Some(modulus)).map(BigUint::from),
+                Some(reduced.clone())
+            );
+            assert_eq!(
+                count_p3::<BigUint>(&lines, days, Some(modulus)),
+                Some(reduced)
+            );
+        }
+    }
+
+    #[test]
+    fn u64_counts_stop_at_overflow() {
+        let lines = read_data("test.txt");
+        let limit = BigUint::from(u64::MAX);
+        let mut overflowed = false;
+        for days in 0..600 {
+            let exact = count_p2::<BigUint>(&lines, days, false).unwrap();
+            match count_p2::<u64>(&lines, days, false) {
+                Ok(count) => assert_eq!(BigUint::from(count), exact),
+                Err(_) => {
+                    assert!(exact > limit, "day {} overflowed at {}", days, exact);
+                    overflowed = true;
+                }
+            }
+            if let Some(count) = count_p3::<u64>(&lines, days, None) {
+                assert_eq!(BigUint::from(count), exact);
+            }
+        }
+        assert!(overflowed);
+        assert_eq!(count_p3::<u64>(&lines, 599, None), None);
+    }
+
+    #[test]
+    fn first_day_exceeding_matches_a_scan() {
+        let counts = get_initial_counts(&read_data("test.txt"));
+        let totals: Vec<BigUint> = get_time_series(&counts, 300)
+            .iter()
+            .map(get_total)
+            .collect();
+        let mut targets: Vec<BigUint> = vec![BigUint::from(0u64), BigUint::from(4u64)];
+        for total in totals.iter().step_by(7) {
+            targets.push(total.clone() - 1u64);
+            targets.push(total.clone());
+            targets.push(total.clone() + 1u64);
+        }
+        for target in targets {
+            let scanned = totals
+                .iter()
+                .position(|total| *total > target)
+                .map(|day| day as u64);
+            assert_eq!(
+                get_first_day_exceeding(&counts, &target),
+                scanned,
+                "target {}",
+                target
+            );
+        }
+        assert_eq!(get_first_day_exceeding(&[0; 9], &BigUint::from(1u64)), None);
+    }
+
+    #[test]
+    fn fixed_stochastic_model_is_part_2() {
+        let lines = read_data("test.txt");
+        let counts = get_initial_counts(&lines);
+        let interval = Distribution::parse("7").unwrap();
+        let newborn = Distribution::parse("9").unwrap();
+        let survival = Survival::parse("1").unwrap();
+        let mut rng = StdRng::seed_from_u64(common::get_trial_seed(0, 0));
+        let totals = simulate_stochastic(&counts, 256, &interval, &newborn, &survival, &mut rng);
+        for (days, total) in totals.iter().enumerate() {
+            assert_eq!(count_p2::<u64>(&lines, days as u64, false), Ok(*total));
+        }
+    }
+
+    #[test]
+    fn bad_distributions_are_rejected() {
+        for s in [
+            "normal:7,0",
+            "normal:0,1",
+            "uniform:0-3",
+            "poisson:0",
+            "0",
+            "x",
+            "fixed:7,8",
+        ] {
+            assert!(Distribution::parse(s).is_err(), "{}", s);
+        }
+        let reversed = Distribution::parse("uniform:8-6").unwrap();
+        let days: Vec<usize> = reversed.outcomes.iter().map(|o| o.0).collect();
+        assert_eq!(days, vec![6, 7, 8]);
+    }
+}
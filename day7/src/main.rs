@@ -1,70 +1,693 @@
 use clap::App;
 use clap::Arg;
-use std::collections::HashMap;
+use clap::ArgMatches;
+use std::convert::TryFrom;
 
-fn get_min_max(positions: &Vec<i32>) -> (i32, i32) {
+fn get_min_max(positions: &[i32]) -> (i32, i32) {
     (
         *positions.iter().min().unwrap(),
         *positions.iter().max().unwrap(),
     )
 }
 
-fn get_p1_cost(positions: &Vec<i32>, position: i32) -> i32 {
-    let mut cost = 0i32;
-    for pos in positions {
-        cost += &(pos - position).abs();
+// How far apart two positions are, which doesn't fit in an i32 for far apart crabs
+fn get_distance(a: i32, b: i32) -> u64 {
+    (a as i64 - b as i64).unsigned_abs()
+}
+
+// 1 + 2 + ... + n
+fn triangular(n: u64) -> Option<u64> {
+    u64::try_from(n as u128 * (n as u128 + 1) / 2).ok()
+}
+
+// A tiny arithmetic language over the distance `d`: integers, + - * / ^, parentheses and
+// min(a, b) / max(a, b), e.g. `d*d + 3*d` or `min(d, 10)^2`
+enum Expr {
+    Distance,
+    Number(i64),
+    Op(char, Box<Expr>, Box<Expr>),
+    Call(String, Box<Expr>, Box<Expr>),
+}
+
+struct ExprParser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl<'a> ExprParser<'a> {
+    fn parse(s: &str) -> Result<Expr, String> {
+        let mut parser = ExprParser {
+            chars: s.chars().peekable(),
+        };
+        let expr = parser.sum()?;
+        if let Some(c) = parser.peek() {
+            return Err(format!("Unexpected '{}' in cost expression {}", c, s));
+        }
+        Ok(expr)
+    }
+    fn peek(&mut self) -> Option<char> {
+        while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+            self.chars.next();
+        }
+        self.chars.peek().copied()
+    }
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.peek() != Some(c) {
+            return Err(format!("Expected '{}' in cost expression", c));
+        }
+        self.chars.next();
+        Ok(())
+    }
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut expr = self.product()?;
+        while let Some(op @ ('+' | '-')) = self.peek() {
+            self.chars.next();
+            expr = Expr::Op(op, Box::new(expr), Box::new(self.product()?));
+        }
+        Ok(expr)
+    }
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut expr = self.power()?;
+        while let Some(op @ ('*' | '/')) = self.peek() {
+            self.chars.next();
+            expr = Expr::Op(op, Box::new(expr), Box::new(self.power()?));
+        }
+        Ok(expr)
+    }
+    // ^ binds tightest and groups to the right
+    fn power(&mut self) -> Result<Expr, String> {
+        let base = self.atom()?;
+        if self.peek() == Some('^') {
+            self.chars.next();
+            return Ok(Expr::Op('^', Box::new(base), Box::new(self.power()?)));
+        }
+        Ok(base)
+    }
+    fn atom(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some('(') => {
+                self.chars.next();
+                let expr = self.sum()?;
+                self.expect(')')?;
+                Ok(expr)
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let mut number = 0i64;
+                while let Some(digit) = self.chars.peek().and_then(|c| c.to_digit(10)) {
+                    number = number
+                        .checked_mul(10)
+                        .and_then(|number| number.checked_add(digit as i64))
+                        .ok_or("Number too big in cost expression")?;
+                    self.chars.next();
+                }
+                Ok(Expr::Number(number))
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                let mut name = String::new();
+                while let Some(&c) = self.chars.peek().filter(|c| c.is_ascii_alphabetic()) {
+                    name.push(c);
+                    self.chars.next();
+                }
+                match name.as_str() {
+                    "d" => Ok(Expr::Distance),
+                    "min" | "max" => {
+                        self.expect('(')?;
+                        let a = self.sum()?;
+                        self.expect(',')?;
+                        let b = self.sum()?;
+                        self.expect(')')?;
+                        Ok(Expr::Call(name, Box::new(a), Box::new(b)))
+                    }
+                    _ => Err(format!("Unknown name {} in cost expression", name)),
+                }
+            }
+            Some(c) => Err(format!("Unexpected '{}' in cost expression", c)),
+            None => Err("Cost expression ends too soon".to_string()),
+        }
     }
+}
+
+impl Expr {
+    fn eval(&self, distance: i64) -> Result<i64, String> {
+        Ok(match self {
+            Expr::Distance => distance,
+            Expr::Number(n) => *n,
+            Expr::Op(op, a, b) => {
+                let (a, b) = (a.eval(distance)?, b.eval(distance)?);
+                let result = match op {
+                    '+' => a.checked_add(b),
+                    '-' => a.checked_sub(b),
+                    '*' => a.checked_mul(b),
+                    '/' if b == 0 => {
+                        return Err(format!(
+                            "Cost expression divides by zero at distance {}",
+                            distance
+                        ))
+                    }
+                    '/' => a.checked_div(b),
+                    _ if b < 0 => {
+                        return Err(format!(
+                            "Cost expression has a negative power at distance {}",
+                            distance
+                        ))
+                    }
+                    _ => u32::try_from(b).ok().and_then(|b| a.checked_pow(b)),
+                };
+                result.ok_or(format!(
+                    "Cost expression overflowed at distance {}",
+                    distance
+                ))?
+            }
+            Expr::Call(name, a, b) => match name.as_str() {
+                "min" => a.eval(distance)?.min(b.eval(distance)?),
+                _ => a.eval(distance)?.max(b.eval(distance)?),
+            },
+        })
+    }
+}
 
-    cost
+// How much fuel a crab burns to move some distance
+enum CostFn {
+    // Part 1
+    Linear,
+    // Part 2
+    Triangular,
+    Quadratic,
+    // Linear, but never more than the cap
+    Capped(u64),
+    // Another cost scaled by how heavy each crab is
+    Weighted(Box<CostFn>, Vec<u64>),
+    Custom(Expr),
 }
 
-fn get_p2_cost(positions: &Vec<i32>, position: i32, known_sums: &mut HashMap<i32, u64>) -> u64 {
-    let mut cost = 0u64;
-    for pos in positions {
-        let diff = &(pos - position).abs();
-        cost += *known_sums
-            .entry(*diff)
-            .or_insert((0u64..(*diff + 1) as u64).sum::<u64>());
+impl CostFn {
+    // `linear`, `triangular`, `quadratic`, `capped:N`, or else a cost expression
+    fn parse(s: &str) -> Result<CostFn, String> {
+        match s.split_once(':') {
+            _ if s == "linear" => Ok(CostFn::Linear),
+            _ if s == "triangular" => Ok(CostFn::Triangular),
+            _ if s == "quadratic" => Ok(CostFn::Quadratic),
+            Some(("capped", cap)) => cap
+                .parse::<u64>()
+                .map(CostFn::Capped)
+                .map_err(|_| format!("Bad cap: {}", cap)),
+            _ => ExprParser::parse(s).map(CostFn::Custom),
+        }
+    }
+    fn cost(&self, crab: usize, distance: u64) -> Result<u64, String> {
+        let overflowed = || format!("Cost overflowed at distance {}", distance);
+        match self {
+            CostFn::Linear => Ok(distance),
+            CostFn::Triangular => triangular(distance).ok_or_else(overflowed),
+            CostFn::Quadratic => distance.checked_mul(distance).ok_or_else(overflowed),
+            CostFn::Capped(cap) => Ok(distance.min(*cap)),
+            CostFn::Weighted(cost_fn, weights) => weights[crab]
+                .checked_mul(cost_fn.cost(crab, distance)?)
+                .ok_or_else(overflowed),
+            CostFn::Custom(expr) => {
+                let cost = expr.eval(i64::try_from(distance).map_err(|_| overflowed())?)?;
+                u64::try_from(cost)
+                    .map_err(|_| format!("Cost expression is negative at distance {}", distance))
+            }
+        }
+    }
+    // Whether every crab's cost is convex along the line, so the total is too. f(|t|) is convex
+    // when f is convex for t >= 0 and doesn't drop from 0 to 1. Weights are never negative, so
+    // they keep the base cost's shape, and only an expression has to be checked, once.
+    fn is_convex(&self, max_distance: u64) -> Result<bool, String> {
+        Ok(match self {
+            CostFn::Linear | CostFn::Triangular | CostFn::Quadratic => true,
+            // Flat past the cap, which is only convex if no crab gets that far
+            CostFn::Capped(cap) => max_distance <= *cap,
+            CostFn::Weighted(cost_fn, _) => cost_fn.is_convex(max_distance)?,
+            CostFn::Custom(_) => {
+                let costs = self.get_costs(max_distance)?;
+                costs.len() < 2
+                    || costs[1] >= costs[0] && costs.windows(3).all(|w| w[0] + w[2] >= 2 * w[1])
+            }
+        })
+    }
+    // Whether a crab never pays less for going further, so each crab is best off at the nearest
+    // gathering point
+    fn is_nondecreasing(&self, max_distance: u64) -> Result<bool, String> {
+        Ok(match self {
+            CostFn::Linear | CostFn::Triangular | CostFn::Quadratic | CostFn::Capped(_) => true,
+            CostFn::Weighted(cost_fn, _) => cost_fn.is_nondecreasing(max_distance)?,
+            CostFn::Custom(_) => self
+                .get_costs(max_distance)?
+                .windows(2)
+                .all(|w| w[0] <= w[1]),
+        })
     }
+    // An unweighted cost at every distance up to the max
+    fn get_costs(&self, max_distance: u64) -> Result<Vec<i128>, String> {
+        (0..max_distance + 1)
+            .map(|d| self.cost(0, d).map(|cost| cost as i128))
+            .collect()
+    }
+}
 
-    cost
+fn get_total_cost(positions: &[i32], cost_fn: &CostFn, position: i32) -> Result<u64, String> {
+    positions
+        .iter()
+        .enumerate()
+        .try_fold(0u64, |total, (crab, pos)| {
+            let cost = cost_fn.cost(crab, get_distance(*pos, position))?;
+            total
+                .checked_add(cost)
+                .ok_or(format!("Total cost overflowed at position {}", position))
+        })
 }
 
-fn get_positions(line: &String) -> Vec<i32> {
-    line.split(",").map(|p| p.parse::<i32>().unwrap()).collect()
+fn get_positions(line: &str) -> Vec<i32> {
+    line.split(',').map(|p| p.parse::<i32>().unwrap()).collect()
 }
 
-fn p1(lines: &Vec<String>) {
-    let positions: Vec<i32> = get_positions(&lines[0]);
-    let (min, max) = get_min_max(&positions);
-    let mut min_cost = i32::MAX;
-    let mut min_pos = i32::MAX;
-    for pos in min..max {
-        let cost = get_p1_cost(&positions, pos);
-        if cost < min_cost {
-            min_cost = cost;
-            min_pos = pos;
+// Crabs on a line, from the puzzle's single comma-separated line, or on a grid, from `x,y` pairs
+// separated by newlines, spaces or semicolons. `3,4` could be either, so a file is only read as
+// a grid when asked to.
+enum Crabs {
+    Line(Vec<i32>),
+    Grid(Vec<(i32, i32)>),
+}
+
+fn get_crabs(lines: &[String], grid: bool) -> Result<Crabs, String> {
+    let tokens: Vec<&str> = lines
+        .iter()
+        .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ';'))
+        .filter(|token| !token.is_empty())
+        .collect();
+    if !grid {
+        return match tokens[..] {
+            [line] => Ok(Crabs::Line(get_positions(line))),
+            _ => Err(
+                "Expected one comma-separated line of crabs, or x,y pairs with --grid".to_string(),
+            ),
+        };
+    }
+    tokens
+        .iter()
+        .map(|pair| match pair.split(',').collect::<Vec<&str>>()[..] {
+            [x, y] => match (x.parse::<i32>(), y.parse::<i32>()) {
+                (Ok(x), Ok(y)) => Ok((x, y)),
+                _ => Err(format!("Bad crab: {}", pair)),
+            },
+            _ => Err(format!("Expected x,y, got {}", pair)),
+        })
+        .collect::<Result<Vec<(i32, i32)>, String>>()
+        .map(Crabs::Grid)
+}
+
+impl Crabs {
+    fn len(&self) -> usize {
+        match self {
+            Crabs::Line(positions) => positions.len(),
+            Crabs::Grid(points) => points.len(),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Distance {
+    // |dx| + |dy|
+    Manhattan,
+    // max(|dx|, |dy|), i.e. diagonal steps are free
+    Chebyshev,
+}
+
+impl Distance {
+    fn between(self, a: (i32, i32), b: (i32, i32)) -> u64 {
+        let dx = get_distance(a.0, b.0);
+        let dy = get_distance(a.1, b.1);
+        match self {
+            Distance::Manhattan => dx + dy,
+            Distance::Chebyshev => dx.max(dy),
+        }
+    }
+}
+
+fn get_total_cost_2d(
+    points: &[(i32, i32)],
+    cost_fn: &CostFn,
+    distance: Distance,
+    cell: (i32, i32),
+) -> Result<u64, String> {
+    points
+        .iter()
+        .enumerate()
+        .try_fold(0u64, |total, (crab, &point)| {
+            let cost = cost_fn.cost(crab, distance.between(point, cell))?;
+            total
+                .checked_add(cost)
+                .ok_or(format!("Total cost overflowed at {},{}", cell.0, cell.1))
+        })
+}
+
+fn get_bounds_2d(points: &[(i32, i32)]) -> ((i32, i32), (i32, i32)) {
+    let xs: Vec<i32> = points.iter().map(|p| p.0).collect();
+    let ys: Vec<i32> = points.iter().map(|p| p.1).collect();
+    (get_min_max(&xs), get_min_max(&ys))
+}
+
+// Every cell in the box around the crabs, column by column, keeping the first cheapest. With a
+// convex cost each column is a convex function of y, so it can be ternary searched. When the cost
+// never drops with distance, no crab pays less than it would for just its x offset, so the
+// columns are tried from the lowest such bound up and the rest skipped once that's too much.
+fn search_2d(
+    points: &[(i32, i32)],
+    cost_fn: &CostFn,
+    distance: Distance,
+    convex: bool,
+    bounded: bool,
+) -> Result<((i32, i32), u64), String> {
+    let ((min_x, max_x), (min_y, max_y)) = get_bounds_2d(points);
+    let xs: Vec<i32> = points.iter().map(|p| p.0).collect();
+    let mut columns = (min_x..max_x + 1)
+        .map(|x| match bounded {
+            true => Ok((get_total_cost(&xs, cost_fn, x)?, x)),
+            false => Ok((0, x)),
+        })
+        .collect::<Result<Vec<(u64, i32)>, String>>()?;
+    columns.sort_unstable();
+    let mut best = ((i32::MAX, i32::MAX), u64::MAX);
+    for (bound, x) in columns {
+        if bound > best.1 {
+            break;
+        }
+        let (y, cost) = find_min(min_y, max_y, convex, |y| {
+            get_total_cost_2d(points, cost_fn, distance, (x, y))
+        })?;
+        if (cost, x) < (best.1, best.0 .0) {
+            best = ((x, y), cost);
         }
     }
+    Ok(best)
+}
 
-    println!("{} (cost: {})", min_pos, min_cost);
+fn brute_force_2d(
+    points: &[(i32, i32)],
+    cost_fn: &CostFn,
+    distance: Distance,
+) -> Result<((i32, i32), u64), String> {
+    search_2d(points, cost_fn, distance, false, false)
 }
 
-fn p2(lines: &Vec<String>) {
-    let mut known_sums: HashMap<i32, u64> = HashMap::new();
-    let positions: Vec<i32> = get_positions(&lines[0]);
-    let (min, max) = get_min_max(&positions);
+// The lowest value with at least half the weight at or below it, which is the first cheapest
+// place to gather for a linear cost
+fn get_weighted_median(values: &[i32], weights: &[u64]) -> i32 {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by_key(|&i| values[i]);
+    let total: u128 = weights.iter().map(|&w| w as u128).sum();
+    let mut below = 0u128;
+    for i in order {
+        below += weights[i] as u128;
+        if 2 * below >= total {
+            return values[i];
+        }
+    }
+    unreachable!()
+}
+
+// Linear Manhattan cost, weighted or not, splits into an x part and a y part, each minimized at
+// its weighted median. Other costs are searched; a convex cost of a norm is convex, which makes
+// that a lot quicker.
+fn minimize_2d(
+    points: &[(i32, i32)],
+    cost_fn: &CostFn,
+    distance: Distance,
+) -> Result<((i32, i32), u64), String> {
+    let weights = match (cost_fn, distance) {
+        (CostFn::Linear, Distance::Manhattan) => Some(vec![1; points.len()]),
+        (CostFn::Weighted(base, weights), Distance::Manhattan) => match **base {
+            CostFn::Linear => Some(weights.clone()),
+            _ => None,
+        },
+        _ => None,
+    };
+    if let Some(weights) = weights {
+        let xs: Vec<i32> = points.iter().map(|p| p.0).collect();
+        let ys: Vec<i32> = points.iter().map(|p| p.1).collect();
+        let cell = (
+            get_weighted_median(&xs, &weights),
+            get_weighted_median(&ys, &weights),
+        );
+        return Ok((cell, get_total_cost_2d(points, cost_fn, distance, cell)?));
+    }
+    let ((min_x, max_x), (min_y, max_y)) = get_bounds_2d(points);
+    let max_distance = distance.between((min_x, min_y), (max_x, max_y));
+    let convex = cost_fn.is_convex(max_distance)?;
+    let bounded = cost_fn.is_nondecreasing(max_distance)?;
+    search_2d(points, cost_fn, distance, convex, bounded)
+}
+
+fn run_2d(points: &[(i32, i32)], method: &str, cost_fn: &CostFn, distance: Distance) {
+    let result = match method {
+        "exact" => minimize_2d(points, cost_fn, distance),
+        _ => brute_force_2d(points, cost_fn, distance),
+    };
+    match result {
+        Ok(((x, y), cost)) => println!("{},{} (cost: {})", x, y, cost),
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+// The first cheapest position in min..=max. With a convex cost, ternary search narrows the range
+// before scanning what's left.
+fn find_min<F: Fn(i32) -> Result<u64, String>>(
+    mut min: i32,
+    mut max: i32,
+    convex: bool,
+    cost: F,
+) -> Result<(i32, u64), String> {
+    while convex && max - min > 2 {
+        let m1 = min + (max - min) / 3;
+        let m2 = max - (max - min) / 3;
+        let (c1, c2) = (cost(m1)?, cost(m2)?);
+        // On a tie the first cheapest position can't be past m2
+        if c1 <= c2 {
+            max = if c1 < c2 { m2 - 1 } else { m2 };
+        } else {
+            min = m1 + 1;
+        }
+    }
     let mut min_cost = u64::MAX;
     let mut min_pos = i32::MAX;
     for pos in min..max + 1 {
-        let cost = get_p2_cost(&positions, pos, &mut known_sums);
+        let cost = cost(pos)?;
         if cost < min_cost {
             min_cost = cost;
             min_pos = pos;
         }
     }
+    Ok((min_pos, min_cost))
+}
+
+// Tries every position between the outermost crabs, keeping the first cheapest
+fn brute_force(positions: &[i32], cost_fn: &CostFn) -> Result<(i32, u64), String> {
+    let (min, max) = get_min_max(positions);
+    find_min(min, max, false, |pos| {
+        get_total_cost(positions, cost_fn, pos)
+    })
+}
+
+// Ternary search when the total cost is convex, otherwise a scan. Either way it lands on the
+// first cheapest position, like brute force.
+fn minimize(positions: &[i32], cost_fn: &CostFn) -> Result<(i32, u64), String> {
+    let (min, max) = get_min_max(positions);
+    let convex = cost_fn.is_convex(get_distance(max, min))?;
+    find_min(min, max, convex, |pos| {
+        get_total_cost(positions, cost_fn, pos)
+    })
+}
+
+// Linear cost is minimized anywhere between the two middle crabs, so take the lower median
+fn solve_p1(positions: &[i32], cost_fn: &CostFn) -> Result<(i32, u64), String> {
+    let mut sorted = positions.to_vec();
+    sorted.sort_unstable();
+    let median = sorted[(sorted.len() - 1) / 2];
+    Ok((median, get_total_cost(positions, cost_fn, median)?))
+}
+
+// The triangular cost's derivative is n·x - Σp + Σsign(x - p)/2, which changes sign within
+// half a step of the mean. So the best integer position is within one of the mean.
+fn solve_p2(positions: &[i32], cost_fn: &CostFn) -> Result<(i32, u64), String> {
+    let sum: i64 = positions.iter().map(|&p| p as i64).sum();
+    let n = positions.len() as i64;
+    let floor_mean = sum.div_euclid(n) as i32;
+    let (min, max) = get_min_max(positions);
+    find_min(
+        (floor_mean - 1).max(min),
+        (floor_mean + 2).min(max),
+        false,
+        |pos| get_total_cost(positions, cost_fn, pos),
+    )
+}
+
+// Where to gather the crabs on a line and what it costs
+type Solver = fn(&[i32], &CostFn) -> Result<(i32, u64), String>;
+
+fn run(crabs: &Crabs, method: &str, solve: Solver, cost_fn: &CostFn, distance: Distance) {
+    let positions = match crabs {
+        Crabs::Line(positions) => positions,
+        Crabs::Grid(points) => return run_2d(points, method, cost_fn, distance),
+    };
+    let result = match method {
+        "exact" => solve(positions, cost_fn),
+        _ => brute_force(positions, cost_fn),
+    };
+    match result {
+        Ok((pos, cost)) => println!("{} (cost: {})", pos, cost),
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+// One gathering point and how many crabs go there
+struct Cluster {
+    position: i32,
+    crabs: usize,
+}
+
+// Most positions × crabs the clustering table can hold
+const MAX_TABLE_CELLS: u64 = 1 << 27;
+
+// Exact k-median on a line. Every crab goes to its nearest gathering point, so each group is a
+// contiguous run of the sorted crabs, and a DP over where each run starts finds the cheapest
+// split. Returns the groups from left to right and the total cost.
+fn get_clusters(
+    positions: &[i32],
+    cost_fn: &CostFn,
+    k: usize,
+) -> Result<(Vec<Cluster>, u64), String> {
+    if k == 0 {
+        return Err("Need at least one cluster".to_string());
+    }
+    let n = positions.len();
+    // Past one cluster per crab, more can't help
+    let k = k.min(n);
+    let (min, max) = get_min_max(positions);
+    let max_distance = get_distance(max, min);
+    if !cost_fn.is_nondecreasing(max_distance)? {
+        return Err("Clustering needs a cost that never drops as the distance grows".to_string());
+    }
+    if (max_distance + 1) * (n as u64 + 1) > MAX_TABLE_CELLS {
+        return Err(format!(
+            "Clustering {} crabs over {} positions needs too big a table",
+            n,
+            max_distance + 1
+        ));
+    }
+    let convex = cost_fn.is_convex(max_distance)?;
+
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by_key(|&crab| positions[crab]);
+    // prefix[p][i] is what the first i sorted crabs pay to get to position min + p
+    let prefix = (min..max + 1)
+        .map(|pos| {
+            let mut sums = vec![0u64; n + 1];
+            for (i, &crab) in order.iter().enumerate() {
+                let distance = get_distance(positions[crab], pos);
+                sums[i + 1] = sums[i]
+                    .checked_add(cost_fn.cost(crab, distance)?)
+                    .ok_or(format!("Total cost overflowed at position {}", pos))?;
+            }
+            Ok(sums)
+        })
+        .collect::<Result<Vec<Vec<u64>>, String>>()?;
+    // groups[i][j - i] is the best single gathering point for sorted crabs i..=j, which is
+    // somewhere between the outermost of them. With a convex cost, adding a crab on the right
+    // never moves the first cheapest point left, and dropping one on the left never does either.
+    // So it's between the points for i..=j-1 and i+1..=j, and each length scans the line about
+    // once: O(n·(n + range)) in all. Otherwise every group scans its whole span.
+    let mut groups: Vec<Vec<(i32, u64)>> = vec![Vec::new(); n];
+    for len in 0..n {
+        for i in 0..n - len {
+            let j = i + len;
+            let (low, high) = if convex && len > 0 {
+                (groups[i][len - 1].0, groups[i + 1][len - 1].0)
+            } else {
+                (positions[order[i]], positions[order[j]])
+            };
+            let group = find_min(low, high, false, |pos| {
+                let sums = &prefix[(pos - min) as usize];
+                Ok(sums[j + 1] - sums[i])
+            })?;
+            groups[i].push(group);
+        }
+    }
+
+    // best[c][j] is the cheapest way to gather the first j sorted crabs at c points, and
+    // start[c][j] is where the last group of that begins
+    let mut best = vec![vec![u64::MAX; n + 1]; k + 1];
+    let mut start = vec![vec![0usize; n + 1]; k + 1];
+    best[0][0] = 0;
+    for c in 1..k + 1 {
+        for j in c..n + 1 {
+            for i in c - 1..j {
+                if best[c - 1][i] == u64::MAX {
+                    continue;
+                }
+                let cost = best[c - 1][i] + groups[i][j - 1 - i].1;
+                if cost < best[c][j] {
+                    best[c][j] = cost;
+                    start[c][j] = i;
+                }
+            }
+        }
+    }
+
+    let mut clusters: Vec<Cluster> = Vec::new();
+    let mut j = n;
+    for c in (1..k + 1).rev() {
+        let i = start[c][j];
+        clusters.push(Cluster {
+            position: groups[i][j - 1 - i].0,
+            crabs: j - i,
+        });
+        j = i;
+    }
+    clusters.reverse();
+    Ok((clusters, best[k][n]))
+}
+
+fn cluster(crabs: &Crabs, cost_fn: &CostFn, k: usize) {
+    let positions = match crabs {
+        Crabs::Line(positions) => positions,
+        Crabs::Grid(_) => {
+            eprintln!("Clustering only works for crabs on a line");
+            return;
+        }
+    };
+    let (clusters, cost) = match get_clusters(positions, cost_fn, k) {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    let points: Vec<String> = clusters.iter().map(|c| c.position.to_string()).collect();
+    println!("{} (cost: {})", points.join(", "), cost);
+    for cluster in &clusters {
+        println!("{}: {} crabs", cluster.position, cluster.crabs);
+    }
+}
+
+// One weight per crab, comma-separated in the same order as the crabs
+fn get_weights(path: &str, crabs: usize) -> Vec<u64> {
+    let weights: Vec<u64> = common::read_file(path)[0]
+        .split(',')
+        .map(|w| w.trim().parse::<u64>().unwrap())
+        .collect();
+    if weights.len() != crabs {
+        panic!("Got {} weights for {} crabs", weights.len(), crabs);
+    }
+    weights
+}
 
-    println!("{} (cost: {})", min_pos, min_cost);
+fn get_cost_fn(matches: &ArgMatches, crabs: &Crabs) -> Result<CostFn, String> {
+    let cost_fn = CostFn::parse(matches.value_of("cost").unwrap())?;
+    Ok(match matches.value_of("weights") {
+        Some(path) => CostFn::Weighted(Box::new(cost_fn), get_weights(path, crabs.len())),
+        None => cost_fn,
+    })
 }
 
 fn main() {
@@ -77,16 +700,275 @@ fn main() {
                 .short("p")
                 .long("part")
                 .value_name("PART")
-                .help("Which part")
+                .help("Which part (1, 2, cost, cluster)")
                 .required(true),
         )
+        .arg(
+            Arg::with_name("method")
+                .short("m")
+                .long("method")
+                .value_name("METHOD")
+                .default_value("exact")
+                .possible_values(&["exact", "brute"])
+                .help("Solve directly or try every position"),
+        )
+        .arg(
+            Arg::with_name("cost")
+                .short("c")
+                .long("cost")
+                .value_name("COST")
+                .default_value("linear")
+                .help("linear, triangular, quadratic, capped:N or an expression in d like 'd*d + 3*d' (cost, cluster)"),
+        )
+        .arg(
+            Arg::with_name("weights")
+                .short("w")
+                .long("weights")
+                .value_name("FILE")
+                .help("Comma-separated weight for each crab, multiplying its cost (cost, cluster)"),
+        )
+        .arg(
+            Arg::with_name("clusters")
+                .short("k")
+                .long("clusters")
+                .value_name("K")
+                .default_value("2")
+                .help("# of gathering points (cluster)"),
+        )
+        .arg(
+            Arg::with_name("distance")
+                .long("distance")
+                .value_name("DISTANCE")
+                .default_value("manhattan")
+                .possible_values(&["manhattan", "chebyshev"])
+                .help("How far apart two cells are, for crabs on a grid"),
+        )
+        .arg(
+            Arg::with_name("grid")
+                .long("grid")
+                .help("Read the crabs as x,y pairs on a grid"),
+        )
+        .arg(
+            Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("FILE")
+                .default_value("/Users/alexeyler/Development/aoc/2021/day7/src/data/crabs.txt")
+                .help("Crab positions, comma-separated or as x,y pairs with --grid"),
+        )
         .get_matches();
     let part = matches.value_of("part").unwrap();
-    let lines: Vec<String> =
-        common::read_file("/Users/alexeyler/Development/aoc/2021/day7/src/data/crabs.txt");
+    let method = matches.value_of("method").unwrap();
+    let distance = match matches.value_of("distance").unwrap() {
+        "manhattan" => Distance::Manhattan,
+        _ => Distance::Chebyshev,
+    };
+    let lines: Vec<String> = common::read_file(matches.value_of("input").unwrap());
+    let crabs = match get_crabs(&lines, matches.is_present("grid")) {
+        Ok(crabs) => crabs,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
     match part {
-        "1" => p1(&lines),
-        "2" => p2(&lines),
+        "1" => run(&crabs, method, solve_p1, &CostFn::Linear, distance),
+        "2" => run(&crabs, method, solve_p2, &CostFn::Triangular, distance),
+        "cost" | "cluster" => {
+            let cost_fn = match get_cost_fn(&matches, &crabs) {
+                Ok(cost_fn) => cost_fn,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            };
+            if part == "cost" {
+                run(&crabs, method, minimize, &cost_fn, distance);
+            } else {
+                let k = matches
+                    .value_of("clusters")
+                    .unwrap()
+                    .parse::<usize>()
+                    .unwrap();
+                cluster(&crabs, &cost_fn, k);
+            }
+        }
         _ => eprintln!("Bad arg value: {}", part),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: [i32; 10] = [16, 1, 2, 0, 4, 2, 7, 1, 2, 14];
+
+    // Small pseudo-random crab lines, the same every run
+    fn get_random_lines() -> Vec<Vec<i32>> {
+        let mut state: u64 = 7;
+        let mut next = |range: u64| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 33) % range
+        };
+        (0..200)
+            .map(|_| {
+                let n = next(12) as usize + 1;
+                (0..n).map(|_| next(41) as i32 - 20).collect()
+            })
+            .collect()
+    }
+
+    // Ties may land on different positions, but never at a different cost
+    fn assert_matches_brute_force(positions: &[i32]) {
+        for (solve, cost_fn) in [
+            (solve_p1 as Solver, CostFn::Linear),
+            (solve_p2, CostFn::Triangular),
+        ] {
+            let (_, exact) = solve(positions, &cost_fn).unwrap();
+            let (_, brute) = brute_force(positions, &cost_fn).unwrap();
+            assert_eq!(exact, brute, "{:?}", positions);
+        }
+    }
+
+    #[test]
+    fn sample() {
+        assert_eq!(solve_p1(&SAMPLE, &CostFn::Linear).unwrap(), (2, 37));
+        assert_eq!(solve_p2(&SAMPLE, &CostFn::Triangular).unwrap(), (5, 168));
+        assert_matches_brute_force(&SAMPLE);
+    }
+
+    #[test]
+    fn one_crab() {
+        assert_eq!(solve_p1(&[9], &CostFn::Linear).unwrap(), (9, 0));
+        assert_eq!(solve_p2(&[9], &CostFn::Triangular).unwrap(), (9, 0));
+        assert_matches_brute_force(&[9]);
+    }
+
+    #[test]
+    fn one_spot() {
+        assert_eq!(solve_p1(&[4, 4, 4], &CostFn::Linear).unwrap(), (4, 0));
+        assert_eq!(solve_p2(&[4, 4, 4], &CostFn::Triangular).unwrap(), (4, 0));
+        assert_matches_brute_force(&[4, 4, 4]);
+    }
+
+    #[test]
+    fn negative_positions() {
+        assert_eq!(solve_p1(&[-7, -1, -3], &CostFn::Linear).unwrap(), (-3, 6));
+        assert_matches_brute_force(&[-7, -1, -3]);
+        assert_matches_brute_force(&[-10, 3, -2, 8, -9]);
+    }
+
+    // Every way to split the sorted crabs into k runs, each gathered by brute force
+    fn get_clusters_brute_force(sorted: &[i32], cost_fn: &CostFn, k: usize) -> u64 {
+        if k == 1 {
+            return brute_force(sorted, cost_fn).unwrap().1;
+        }
+        (1..sorted.len() - k + 2)
+            .map(|split| {
+                brute_force(&sorted[..split], cost_fn).unwrap().1
+                    + get_clusters_brute_force(&sorted[split..], cost_fn, k - 1)
+            })
+            .min()
+            .unwrap()
+    }
+
+    #[test]
+    fn clusters_match_brute_force() {
+        let cost_fns = [
+            CostFn::Linear,
+            CostFn::Triangular,
+            CostFn::Capped(5),
+            CostFn::Custom(ExprParser::parse("min(d, 6) * d").unwrap()),
+        ];
+        for mut positions in get_random_lines() {
+            positions.sort_unstable();
+            for cost_fn in &cost_fns {
+                for k in 1..positions.len().min(3) + 1 {
+                    let (_, cost) = get_clusters(&positions, cost_fn, k).unwrap();
+                    let brute = get_clusters_brute_force(&positions, cost_fn, k);
+                    assert_eq!(cost, brute, "{:?} k={}", positions, k);
+                }
+            }
+        }
+        let drops = CostFn::Custom(ExprParser::parse("10 - d").unwrap());
+        assert!(get_clusters(&SAMPLE, &drops, 2).is_err());
+        assert!(get_clusters(&SAMPLE, &CostFn::Linear, 0).is_err());
+    }
+
+    #[test]
+    fn grids_match_brute_force() {
+        let lines = get_random_lines();
+        for pair in lines.chunks(2) {
+            let points: Vec<(i32, i32)> = pair[0]
+                .iter()
+                .zip(pair[1].iter())
+                .map(|(&x, &y)| (x, y))
+                .collect();
+            if points.is_empty() {
+                continue;
+            }
+            let weights = points
+                .iter()
+                .map(|&(x, y)| (x + y + 40) as u64 % 4)
+                .collect();
+            let cost_fns = [
+                CostFn::Linear,
+                CostFn::Triangular,
+                CostFn::Capped(5),
+                CostFn::Weighted(Box::new(CostFn::Linear), weights),
+            ];
+            for cost_fn in &cost_fns {
+                for &distance in &[Distance::Manhattan, Distance::Chebyshev] {
+                    let (_, exact) = minimize_2d(&points, cost_fn, distance).unwrap();
+                    let (_, brute) = brute_force_2d(&points, cost_fn, distance).unwrap();
+                    assert_eq!(exact, brute, "{:?}", points);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn grid_needs_flag() {
+        let lines = vec!["3,4".to_string()];
+        assert!(matches!(get_crabs(&lines, false), Ok(Crabs::Line(p)) if p == [3, 4]));
+        assert!(matches!(get_crabs(&lines, true), Ok(Crabs::Grid(p)) if p == [(3, 4)]));
+        let lines = vec!["3,4".to_string(), "5,6".to_string()];
+        assert!(get_crabs(&lines, false).is_err());
+        assert!(get_crabs(&["1,2,3".to_string()], true).is_err());
+        assert!(get_crabs(&["3,x".to_string()], true).is_err());
+    }
+
+    #[test]
+    fn bad_costs() {
+        for cost in [
+            "d*",
+            "capped:x",
+            "min(d, 2",
+            "e + 1",
+            "99999999999999999999 * d",
+            "d ) ",
+        ] {
+            assert!(CostFn::parse(cost).is_err(), "{}", cost);
+        }
+        assert!(CostFn::parse("9223372036854775807 - d").is_ok());
+    }
+
+    #[test]
+    fn cost_errors() {
+        let divide = CostFn::Custom(ExprParser::parse("10 + d/(d-3)").unwrap());
+        assert_eq!(
+            minimize(&SAMPLE, &divide).unwrap_err(),
+            "Cost expression divides by zero at distance 3"
+        );
+        assert!(get_total_cost(&[0, i32::MAX], &CostFn::Quadratic, i32::MIN).is_err());
+    }
+
+    #[test]
+    fn random_lines() {
+        for positions in get_random_lines() {
+            assert_matches_brute_force(&positions);
+        }
+    }
+}
@@ -1,168 +1,792 @@
 use clap::App;
 use clap::Arg;
-use std::collections::HashMap;
-
-fn get_patterns_outputs(lines: &Vec<String>) -> (Vec<Vec<&str>>, Vec<Vec<&str>>) {
-    let mut patterns: Vec<Vec<&str>> = Vec::new();
-    let mut outputs: Vec<Vec<&str>> = Vec::new();
-    for line in lines {
-        let parts: Vec<&str> = line.split("|").filter(|p| !p.is_empty()).collect();
-        let line_patterns_untokenized = parts[0];
-        let line_outputs_untokenized = parts[1];
-        let line_patterns = line_patterns_untokenized
-            .split(" ")
-            .filter(|p| !p.is_empty())
-            .collect();
-        let line_outputs = line_outputs_untokenized
-            .split(" ")
-            .filter(|p| !p.is_empty())
-            .collect();
-        patterns.push(line_patterns);
-        outputs.push(line_outputs);
-    }
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
 
-    (patterns, outputs)
+// Display alphabets are written as a `segments` line naming the segments, then a symbol and
+// its lit segments per line. The segment line can be left out when the names are a, b, c...
+//
+// The puzzle's display, with segments a-g laid out as
+//  aaaa
+// b    c
+// b    c
+//  dddd
+// e    f
+// e    f
+//  gggg
+const SEVEN_SEGMENT: &str = "segments abcdefg
+0 abcefg
+1 cf
+2 acdeg
+3 acdfg
+4 bcdf
+5 abdfg
+6 abdefg
+7 acf
+8 abcdefg
+9 abcdfg
+";
+// Added to the seven segment digits for hex
+const HEX_LETTERS: &str = "A abcdef
+b bdefg
+C abeg
+d cdefg
+E abdeg
+F abde
+";
+// Segments a-f go clockwise around the outside from the top, g and h are the left and right
+// halves of the middle bar, i-k the top diagonal, vertical and diagonal, and l-n the bottom ones
+//  aaaaaaa
+// fi  j  kb
+// f i j k b
+//  ggg hhh
+// e l m n c
+// el  m  nc
+//  ddddddd
+const FOURTEEN_SEGMENT: &str = "segments abcdefghijklmn
+0 abcdefkl
+1 bc
+2 abdegh
+3 abcdh
+4 bcfgh
+5 adfgn
+6 acdefgh
+7 abc
+8 abcdefgh
+9 abcdfgh
+A abcefgh
+B abcdhjm
+C adef
+D abcdjm
+E adefgh
+F aefg
+G acdefh
+H bcefgh
+I adjm
+J bcde
+K efgkn
+L def
+M bcefik
+N bcefin
+O abcdef
+P abefgh
+Q abcdefn
+R abefghn
+S acdfgh
+T ajm
+U bcdef
+V efkl
+W bcefln
+X ikln
+Y ikm
+Z adkl
+";
+// The fourteen segment display with its top and bottom bars split in two: a-b across the top,
+// c-d down the right, e-f across the bottom (right to left), g-h up the left, i-j the middle
+// bar, k-m the top diagonal, vertical and diagonal, and n-p the bottom ones
+const SIXTEEN_SEGMENT: &str = "segments abcdefghijklmnop
+0 abcdefghmn
+1 cd
+2 abcefgij
+3 abcdefj
+4 cdhij
+5 abefhip
+6 abdefghij
+7 abcd
+8 abcdefghij
+9 abcdefhij
+A abcdghij
+B abcdefjlo
+C abefgh
+D abcdeflo
+E abefghij
+F abghi
+G abdefghj
+H cdghij
+I abeflo
+J cdefg
+K ghimp
+L efgh
+M cdghkm
+N cdghkp
+O abcdefgh
+P abcghij
+Q abcdefghp
+R abcghijp
+S abdefhij
+T ablo
+U cdefgh
+V ghmn
+W cdghnp
+X kmnp
+Y kmo
+Z abefmn
+";
+// Stop looking for wirings after this many, so an empty line doesn't list all n! of them
+const MAX_CANDIDATES: usize = 10;
+// Enough to find every seven segment wiring (7!), so a reading is only trusted once they've all
+// been checked
+const MAX_READING_WIRINGS: usize = 5040;
+
+// The symbols a display can show, and which segments each one lights
+struct Alphabet {
+    segments: Vec<char>,
+    symbols: Vec<String>,
+    shapes: Vec<u32>,
 }
 
-fn get_segment_map<'a>(pattern: &Vec<&'a str>) -> HashMap<&'a str, usize> {
-    let mut segments: HashMap<usize, &str> = HashMap::new();
-    let mut unknown_segments: Vec<&str> = Vec::new();
-    for segment in pattern {
-        match segment.len() {
-            2 => {
-                segments.insert(1, segment);
-                ()
+impl Alphabet {
+    fn parse(definition: &str) -> Alphabet {
+        let lines: Vec<&str> = definition
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+        let segments: Vec<char> = match lines.iter().find_map(|l| l.strip_prefix("segments ")) {
+            Some(segments) => segments.trim().chars().collect(),
+            None => {
+                let mut segments: Vec<char> = lines
+                    .iter()
+                    .filter_map(|line| line.split_whitespace().nth(1))
+                    .flat_map(|shape| shape.chars())
+                    .collect();
+                segments.sort_unstable();
+                segments.dedup();
+                segments
             }
-            3 => {
-                segments.insert(7, segment);
-                ()
+        };
+        if segments.len() > 32 {
+            panic!("A display can have at most 32 segments");
+        }
+        let mut alphabet = Alphabet {
+            segments,
+            symbols: Vec::new(),
+            shapes: Vec::new(),
+        };
+        for line in lines.iter().filter(|l| !l.starts_with("segments ")) {
+            let mut words = line.split_whitespace();
+            let symbol = words.next().unwrap();
+            let shape = alphabet.get_mask(words.next().unwrap_or(""));
+            if let Some(i) = alphabet.shapes.iter().position(|&s| s == shape) {
+                panic!(
+                    "{} and {} light the same segments",
+                    alphabet.symbols[i], symbol
+                );
             }
-            4 => {
-                segments.insert(4, segment);
-                ()
+            alphabet.symbols.push(String::from(symbol));
+            alphabet.shapes.push(shape);
+        }
+        alphabet
+    }
+
+    // One of the built-in alphabets, or else a definition file
+    fn load(name: &str) -> Alphabet {
+        match name {
+            "seven" => Alphabet::parse(SEVEN_SEGMENT),
+            "hex" => Alphabet::parse(&format!("{}{}", SEVEN_SEGMENT, HEX_LETTERS)),
+            "fourteen" => Alphabet::parse(FOURTEEN_SEGMENT),
+            "sixteen" => Alphabet::parse(SIXTEEN_SEGMENT),
+            path => Alphabet::parse(&common::read_file(path).join("\n")),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.segments.len()
+    }
+
+    fn letter(&self, segment: usize) -> char {
+        self.segments[segment]
+    }
+
+    // Wires or segments as bits, in the order the alphabet lists them
+    fn get_mask(&self, letters: &str) -> u32 {
+        letters.chars().fold(0, |mask, c| {
+            match self.segments.iter().position(|&s| s == c) {
+                Some(bit) => mask | 1 << bit,
+                None => panic!("No segment {} on this display", c),
             }
-            7 => {
-                segments.insert(8, segment);
-                ()
+        })
+    }
+}
+
+// One display: its scrambled symbol patterns and the scrambled outputs. Either side may be
+// incomplete. A pattern with nothing lit is written as `-`.
+struct Display<'a> {
+    patterns: Vec<&'a str>,
+    outputs: Vec<&'a str>,
+}
+
+fn get_displays<'a>(lines: &'a [String]) -> Vec<Display<'a>> {
+    lines
+        .iter()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (patterns, outputs) = line.split_once('|').unwrap_or((line, ""));
+            let get_tokens = |side: &'a str| {
+                side.split_whitespace()
+                    .map(|token| if token == "-" { "" } else { token })
+                    .collect()
+            };
+            Display {
+                patterns: get_tokens(patterns),
+                outputs: get_tokens(outputs),
             }
-            _ => unknown_segments.push(segment),
-        };
+        })
+        .collect()
+}
+
+// A wiring maps each wire (by index) to the segment it lights
+type Wiring = Vec<usize>;
+
+// Backtracking search for every wiring under which each observed pattern lights up some symbol.
+// Faulty wires are ignored: whatever they show says nothing about their segment.
+struct Decoder<'a> {
+    alphabet: &'a Alphabet,
+    observed: Vec<u32>,
+    faulty: u32,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(alphabet: &'a Alphabet, display: &Display, faulty: u32) -> Decoder<'a> {
+        let mut observed: Vec<u32> = display
+            .patterns
+            .iter()
+            .chain(display.outputs.iter())
+            .map(|p| alphabet.get_mask(p))
+            .collect();
+        observed.sort_unstable();
+        observed.dedup();
+        Decoder {
+            alphabet,
+            observed,
+            faulty,
+        }
     }
-    if !segments.contains_key(&1)
-        || !segments.contains_key(&4)
-        || !segments.contains_key(&7)
-        || !segments.contains_key(&8)
-    {
-        panic!("Couldn't find patterns for 1, 4, 7, or 8");
-    }
-    for segment in unknown_segments {
-        if segment.len() == 5 {
-            let segment_without_one: Vec<char> = segment
-                .chars()
-                .filter(|&c| !segments[&1].contains(c))
-                .collect();
-            // A 3 is the only 5-length digit that contains all the segments in a one digit
-            if segment_without_one.len() == 3 {
-                segments.insert(3, segment);
-            } else {
-                let segment_without_four: Vec<char> = segment
-                    .chars()
-                    .filter(|&c| !segments[&4].contains(c))
-                    .collect();
-                if segment_without_four.len() == 3 {
-                    segments.insert(2, segment);
-                } else {
-                    segments.insert(5, segment);
+
+    // Whether every pattern could still be a symbol. The wires assigned so far pin down some
+    // segments that must be lit and some that must be dark.
+    fn is_consistent(&self, wiring: &[Option<usize>]) -> bool {
+        self.observed.iter().all(|&pattern| {
+            let mut lit = 0u32;
+            let mut dark = 0u32;
+            for (wire, segment) in wiring.iter().enumerate() {
+                if self.faulty & 1 << wire != 0 {
+                    continue;
+                }
+                if let Some(segment) = segment {
+                    if pattern & 1 << wire != 0 {
+                        lit |= 1 << segment;
+                    } else {
+                        dark |= 1 << segment;
+                    }
                 }
             }
-        } else if segment.len() == 6 {
-            let segment_without_one: Vec<char> = segment
-                .chars()
-                .filter(|&c| !segments[&1].contains(c))
-                .collect();
-            // A 6 is the only 6-length digit that does not contain all the segments in a one digit
-            if segment_without_one.len() == 5 {
-                segments.insert(6, segment);
-            } else {
-                let segment_without_four: Vec<char> = segment
-                    .chars()
-                    .filter(|&c| !segments[&4].contains(c))
-                    .collect();
-                // A 9 is the only 6-length digit that does not contain all the segments in a four digit
-                if segment_without_four.len() == 2 {
-                    segments.insert(9, segment);
-                } else {
-                    // A 0 is the only other remaining 6-length digit
-                    segments.insert(0, segment);
+            // Faulty wires might be hiding lit segments, or showing dark ones
+            let working = (pattern & !self.faulty).count_ones();
+            self.alphabet.shapes.iter().any(|&shape| {
+                (working..working + self.faulty.count_ones() + 1).contains(&shape.count_ones())
+                    && shape & lit == lit
+                    && shape & dark == 0
+            })
+        })
+    }
+
+    fn search(
+        &self,
+        wiring: &mut Vec<Option<usize>>,
+        used: u32,
+        limit: usize,
+        wirings: &mut Vec<Wiring>,
+    ) {
+        if wirings.len() >= limit {
+            return;
+        }
+        // Fill in the wire with the fewest segments left to try
+        let mut best: Option<(usize, Vec<usize>)> = None;
+        for wire in 0..self.alphabet.len() {
+            if wiring[wire].is_some() {
+                continue;
+            }
+            let mut options = Vec::new();
+            for segment in (0..self.alphabet.len()).filter(|&s| used & 1 << s == 0) {
+                wiring[wire] = Some(segment);
+                if self.is_consistent(wiring) {
+                    options.push(segment);
                 }
             }
+            wiring[wire] = None;
+            if best.as_ref().is_none_or(|b| options.len() < b.1.len()) {
+                best = Some((wire, options));
+            }
         }
+        let (wire, options) = match best {
+            Some(best) => best,
+            None => {
+                wirings.push(wiring.iter().map(|s| s.unwrap()).collect());
+                return;
+            }
+        };
+        for segment in options {
+            wiring[wire] = Some(segment);
+            self.search(wiring, used | 1 << segment, limit, wirings);
+        }
+        wiring[wire] = None;
     }
 
-    let mut invert_hash_map: HashMap<&'a str, usize> = HashMap::new();
-    for segment in segments {
-        invert_hash_map.insert(segment.1, segment.0);
+    // Up to `limit` wirings that explain everything observed
+    fn get_wirings(&self, limit: usize) -> Vec<Wiring> {
+        let mut wirings = Vec::new();
+        self.search(&mut vec![None; self.alphabet.len()], 0, limit, &mut wirings);
+        wirings
     }
 
-    invert_hash_map
+    // The symbol the pattern shows, unless faulty segments leave more than one possibility
+    fn get_symbol(&self, wiring: &[usize], pattern: &str) -> Option<&'a str> {
+        let mask = self.alphabet.get_mask(pattern);
+        let mut lit = 0u32;
+        let mut known = 0u32;
+        for wire in (0..self.alphabet.len()).filter(|&w| self.faulty & 1 << w == 0) {
+            known |= 1 << wiring[wire];
+            if mask & 1 << wire != 0 {
+                lit |= 1 << wiring[wire];
+            }
+        }
+        let alphabet = self.alphabet;
+        let mut symbols = (0..alphabet.shapes.len()).filter(|&i| alphabet.shapes[i] & known == lit);
+        match (symbols.next(), symbols.next()) {
+            (Some(i), None) => Some(&alphabet.symbols[i]),
+            _ => None,
+        }
+    }
+
+    // The outputs read left to right
+    fn get_symbols(&self, wiring: &[usize], outputs: &[&str]) -> Option<Vec<&'a str>> {
+        outputs
+            .iter()
+            .map(|output| self.get_symbol(wiring, output))
+            .collect()
+    }
+
+    fn get_reading(&self, wiring: &[usize], outputs: &[&str]) -> Option<String> {
+        Some(self.get_symbols(wiring, outputs)?.concat())
+    }
 }
 
-fn get_digit(
-    segments: &HashMap<&str, usize>,
-    output_segment: &str,
-) -> std::result::Result<usize, &'static str> {
-    for entry in segments {
-        if entry.0.len() != output_segment.len() {
-            continue;
+enum Decoding {
+    Unique(Wiring),
+    // Every wiring found (maybe not all of them), and whether the search stopped early
+    Ambiguous(Vec<Wiring>, bool),
+    Inconsistent,
+}
+
+fn decode(decoder: &Decoder, limit: usize) -> Decoding {
+    let mut wirings = decoder.get_wirings(limit);
+    match wirings.len() {
+        0 => Decoding::Inconsistent,
+        1 => Decoding::Unique(wirings.remove(0)),
+        n => Decoding::Ambiguous(wirings, n >= limit),
+    }
+}
+
+// The segment each wire lights, in wire order, e.g. "deafgbc" when wire a lights segment d
+fn format_wiring(alphabet: &Alphabet, wiring: &[usize]) -> String {
+    wiring
+        .iter()
+        .map(|&segment| alphabet.letter(segment))
+        .collect()
+}
+
+// What the display shows, as long as every candidate wiring agrees on it. Wirings the search
+// never got to might not, so a cut short search reads nothing.
+fn get_output<'a>(alphabet: &'a Alphabet, display: &Display) -> Result<Vec<&'a str>, String> {
+    if display.outputs.is_empty() {
+        return Err(String::from("no outputs to read"));
+    }
+    let decoder = Decoder::new(alphabet, display, 0);
+    let wirings = match decode(&decoder, MAX_READING_WIRINGS) {
+        Decoding::Unique(wiring) => vec![wiring],
+        Decoding::Ambiguous(_, true) => {
+            return Err(String::from(
+                "too many wirings fit these patterns to be sure of the output",
+            ))
         }
-        let segment_chars = entry.0.chars();
-        let intersection: Vec<char> = segment_chars
-            .filter(|&c| !output_segment.contains(c))
-            .collect();
-        if intersection.len() == 0 {
-            return Ok(*entry.1);
+        Decoding::Ambiguous(wirings, false) => wirings,
+        Decoding::Inconsistent => {
+            return Err(String::from(
+                "no wiring fits these patterns (try -p diagnose)",
+            ))
+        }
+    };
+    let readings: Vec<Option<Vec<&str>>> = wirings
+        .iter()
+        .map(|wiring| decoder.get_symbols(wiring, &display.outputs))
+        .collect();
+    match &readings[0] {
+        Some(reading) if readings.iter().all(|r| r.as_ref() == Some(reading)) => {
+            Ok(reading.clone())
         }
+        _ => Err(String::from("the output could be read more than one way")),
     }
-    Err("Could not find segment in map")
 }
 
-fn p1(lines: &Vec<String>) {
-    let (_, outputs) = get_patterns_outputs(&lines);
+// Counts outputs whose segment count gives away the symbol, like 1, 4, 7 and 8 in the puzzle
+fn get_easy_count(lines: &[String], alphabet: &Alphabet) -> i32 {
+    let displays = get_displays(lines);
     let mut count = 0i32;
-    for output in outputs {
-        for segment in output {
-            count += match segment.len() {
-                2 | 3 | 4 | 7 => 1,
-                _ => 0,
+    for display in displays {
+        for segment in display.outputs {
+            let same_length = alphabet
+                .shapes
+                .iter()
+                .filter(|shape| shape.count_ones() as usize == segment.chars().count())
+                .count();
+            if same_length == 1 {
+                count += 1;
             }
         }
     }
+    count
+}
 
-    println!("{}", count);
+fn p1(lines: &[String], alphabet: &Alphabet) {
+    println!("{}", get_easy_count(lines, alphabet));
 }
 
-fn p2(lines: &Vec<String>) {
-    let (patterns, outputs) = get_patterns_outputs(&lines);
-    let mut sum: i32 = 0;
-    for i in 0..patterns.len() {
-        let pattern = &patterns[i];
-        let output = &outputs[i];
-        let segments = get_segment_map(&pattern);
-        if segments.len() != 10 {
-            panic!(format!("Missing segment entry for pattern {:?}", pattern));
+// Big seven segment art for some symbols, five rows tall. Only works for alphabets whose
+// segments are the puzzle's a-g, in whatever order they're listed.
+fn render(alphabet: &Alphabet, reading: &[&str]) -> Option<Vec<String>> {
+    if alphabet.len() != 7 {
+        return None;
+    }
+    // The bit for each of a-g
+    let bits: Vec<usize> = "abcdefg"
+        .chars()
+        .map(|c| alphabet.segments.iter().position(|&s| s == c))
+        .collect::<Option<Vec<usize>>>()?;
+    let mut rows = vec![String::new(); 5];
+    for symbol in reading {
+        let i = alphabet.symbols.iter().position(|s| s == symbol)?;
+        let lit = |segment: usize| alphabet.shapes[i] & 1 << bits[segment] != 0;
+        let bar = |segment: usize| if lit(segment) { " -- " } else { "    " };
+        let sides = |left: usize, right: usize| {
+            format!(
+                "{}  {}",
+                if lit(left) { '|' } else { ' ' },
+                if lit(right) { '|' } else { ' ' }
+            )
+        };
+        rows[0] += bar(0);
+        rows[1] += &sides(1, 2);
+        rows[2] += bar(3);
+        rows[3] += &sides(4, 5);
+        rows[4] += bar(6);
+        for row in rows.iter_mut() {
+            row.push(' ');
         }
-        let mut number_string: String = String::new();
-        for segment in output {
-            let digit = get_digit(&segments, segment).unwrap();
-            number_string += &digit.to_string();
+    }
+    Some(rows)
+}
+
+fn p2(lines: &[String], alphabet: &Alphabet) {
+    let displays = get_displays(lines);
+    let mut sum: u64 = 0;
+    for display in &displays {
+        let outputs = display.outputs.join(" ");
+        let reading = match get_output(alphabet, display) {
+            Ok(reading) => reading,
+            Err(e) => {
+                println!("{}: {}", outputs, e);
+                continue;
+            }
+        };
+        let counted = match reading.concat().parse::<u64>() {
+            Ok(number) => {
+                sum += number;
+                String::new()
+            }
+            Err(_) => String::from(" (not counted)"),
+        };
+        match render(alphabet, &reading) {
+            Some(rows) => {
+                for (i, row) in rows.iter().enumerate() {
+                    if i == 2 {
+                        println!("{}   {}{}", row, outputs, counted);
+                    } else {
+                        println!("{}", row.trim_end());
+                    }
+                }
+            }
+            None => println!("{}: {}{}", outputs, reading.join(""), counted),
         }
-        let number = number_string.parse::<i32>().unwrap();
-        sum += number;
-        println!("{:?}: {}", output, number);
     }
     println!("Total: {}", sum);
 }
 
+// Reports how well each display's wiring is pinned down
+fn decode_all(lines: &[String], alphabet: &Alphabet) {
+    let displays = get_displays(lines);
+    for (i, display) in displays.iter().enumerate() {
+        let decoder = Decoder::new(alphabet, display, 0);
+        match decode(&decoder, MAX_CANDIDATES) {
+            Decoding::Unique(wiring) => {
+                let reading = decoder.get_reading(&wiring, &display.outputs);
+                println!(
+                    "{}: unique wiring {} -> {}",
+                    i + 1,
+                    format_wiring(alphabet, &wiring),
+                    reading.unwrap_or_else(|| String::from("?"))
+                );
+            }
+            Decoding::Ambiguous(wirings, more) => {
+                println!(
+                    "{}: ambiguous, {}{} candidate wirings",
+                    i + 1,
+                    if more { "at least " } else { "" },
+                    wirings.len()
+                );
+                for wiring in &wirings {
+                    let reading = decoder.get_reading(wiring, &display.outputs);
+                    println!(
+                        "    {} -> {}",
+                        format_wiring(alphabet, wiring),
+                        reading.unwrap_or_else(|| String::from("?"))
+                    );
+                }
+            }
+            Decoding::Inconsistent => println!("{}: inconsistent, no wiring fits", i + 1),
+        }
+    }
+}
+
+// Some wires that might be broken, and the wirings that explain the display if they are. `more`
+// is whether the search stopped at MAX_CANDIDATES wirings, so there could be others.
+struct Diagnosis {
+    faulty: u32,
+    wirings: Vec<Wiring>,
+    more: bool,
+}
+
+// The smallest sets of broken wires that make the display consistent, stopping after
+// MAX_CANDIDATES of them. A wire stuck on is lit in every pattern and one stuck off in none, so
+// only those are suspects. Swapped wires aren't a fault here: they look exactly like a different
+// wiring, which the decoder already allows for.
+fn diagnose(alphabet: &Alphabet, display: &Display) -> Vec<Diagnosis> {
+    let decoder = Decoder::new(alphabet, display, 0);
+    let suspects: Vec<usize> = (0..alphabet.len())
+        .filter(|&wire| {
+            let lit = decoder
+                .observed
+                .iter()
+                .filter(|&&p| p & 1 << wire != 0)
+                .count();
+            lit == 0 || lit == decoder.observed.len()
+        })
+        .collect();
+    for faults in 0..suspects.len() + 1 {
+        let diagnoses: Vec<Diagnosis> = (0u32..1 << suspects.len())
+            .filter(|subset| subset.count_ones() as usize == faults)
+            .filter_map(|subset| {
+                let faulty = (0..suspects.len())
+                    .filter(|&i| subset & 1 << i != 0)
+                    .fold(0u32, |faulty, i| faulty | 1 << suspects[i]);
+                let wirings = Decoder::new(alphabet, display, faulty).get_wirings(MAX_CANDIDATES);
+                if wirings.is_empty() {
+                    None
+                } else {
+                    let more = wirings.len() >= MAX_CANDIDATES;
+                    Some(Diagnosis {
+                        faulty,
+                        wirings,
+                        more,
+                    })
+                }
+            })
+            .take(MAX_CANDIDATES)
+            .collect();
+        if !diagnoses.is_empty() {
+            return diagnoses;
+        }
+    }
+    Vec::new()
+}
+
+// e.g. "wire c stuck off (segment e or g)", listing the segments the wire drives in the wirings
+// found. When the search stopped early those are only examples.
+fn format_fault(
+    alphabet: &Alphabet,
+    display: &Display,
+    diagnosis: &Diagnosis,
+    wire: usize,
+) -> String {
+    let stuck_on = display
+        .patterns
+        .iter()
+        .chain(display.outputs.iter())
+        .all(|p| alphabet.get_mask(p) & 1 << wire != 0);
+    let mut segments: Vec<usize> = diagnosis.wirings.iter().map(|w| w[wire]).collect();
+    segments.sort_unstable();
+    segments.dedup();
+    let segments: Vec<String> = segments
+        .iter()
+        .map(|&s| alphabet.letter(s).to_string())
+        .collect();
+    format!(
+        "wire {} stuck {} ({}segment {})",
+        alphabet.letter(wire),
+        if stuck_on { "on" } else { "off" },
+        if diagnosis.more { "e.g. " } else { "" },
+        segments.join(" or ")
+    )
+}
+
+// What the outputs most likely say, with a ? for any symbol the faults leave unclear
+fn get_likely_reading(alphabet: &Alphabet, display: &Display, diagnosis: &Diagnosis) -> String {
+    let decoder = Decoder::new(alphabet, display, diagnosis.faulty);
+    display
+        .outputs
+        .iter()
+        .map(|output| {
+            let mut symbols = diagnosis
+                .wirings
+                .iter()
+                .map(|wiring| decoder.get_symbol(wiring, output));
+            let first = symbols.next().flatten();
+            match first {
+                Some(symbol) if symbols.all(|s| s == first) => symbol,
+                _ => "?",
+            }
+        })
+        .collect()
+}
+
+// Finds the likely broken segments on each display that no wiring explains
+fn diagnose_all(lines: &[String], alphabet: &Alphabet) {
+    let displays = get_displays(lines);
+    for (i, display) in displays.iter().enumerate() {
+        if !Decoder::new(alphabet, display, 0).get_wirings(1).is_empty() {
+            println!("{}: ok", i + 1);
+            continue;
+        }
+        let diagnoses = diagnose(alphabet, display);
+        if diagnoses.is_empty() {
+            println!("{}: no set of stuck wires explains this display", i + 1);
+            continue;
+        }
+        let faults = diagnoses[0].faulty.count_ones();
+        println!(
+            "{}: {} fault{}, {}{} way{} to explain it",
+            i + 1,
+            faults,
+            if faults == 1 { "" } else { "s" },
+            if diagnoses.len() >= MAX_CANDIDATES {
+                "at least "
+            } else {
+                ""
+            },
+            diagnoses.len(),
+            if diagnoses.len() == 1 { "" } else { "s" }
+        );
+        for diagnosis in &diagnoses {
+            let faults: Vec<String> = (0..alphabet.len())
+                .filter(|&wire| diagnosis.faulty & 1 << wire != 0)
+                .map(|wire| format_fault(alphabet, display, diagnosis, wire))
+                .collect();
+            println!(
+                "    {}; reads {}",
+                faults.join(", "),
+                get_likely_reading(alphabet, display, diagnosis)
+            );
+        }
+    }
+}
+
+// Writes random displays in the input format: each shows random symbols through a random wiring.
+// Dropping patterns makes them harder to decode, and faults stick random wires on or off. What
+// each line really says goes to stderr.
+fn generate(alphabet: &Alphabet, options: &GenerateOptions) {
+    let mut rng = StdRng::seed_from_u64(options.seed);
+    let segments = alphabet.len();
+    for line in 0..options.count {
+        let mut wiring: Wiring = (0..segments).collect();
+        wiring.shuffle(&mut rng);
+        // Which wire drives each segment
+        let mut wires = vec![0; segments];
+        for (wire, &segment) in wiring.iter().enumerate() {
+            wires[segment] = wire;
+        }
+        let mut faulty: Vec<usize> = (0..segments).collect();
+        faulty.shuffle(&mut rng);
+        faulty.truncate(options.faults);
+        let stuck_on: Vec<bool> = faulty.iter().map(|_| rng.gen()).collect();
+
+        let scramble = |shape: u32, rng: &mut StdRng| -> String {
+            let mut lit: Vec<usize> = (0..segments)
+                .filter(|&segment| shape & 1 << segment != 0)
+                .map(|segment| wires[segment])
+                .filter(|wire| !faulty.contains(wire))
+                .collect();
+            for (&wire, &on) in faulty.iter().zip(stuck_on.iter()) {
+                if on {
+                    lit.push(wire);
+                }
+            }
+            if lit.is_empty() {
+                return String::from("-");
+            }
+            lit.shuffle(rng);
+            lit.iter().map(|&wire| alphabet.letter(wire)).collect()
+        };
+        let mut patterns: Vec<String> = alphabet
+            .shapes
+            .iter()
+            .map(|&s| scramble(s, &mut rng))
+            .collect();
+        let shown: Vec<usize> = (0..options.digits)
+            .map(|_| rng.gen_range(0..alphabet.shapes.len()))
+            .collect();
+        let outputs: Vec<String> = shown
+            .iter()
+            .map(|&i| scramble(alphabet.shapes[i], &mut rng))
+            .collect();
+        patterns.shuffle(&mut rng);
+        patterns.truncate(alphabet.shapes.len().saturating_sub(options.drop));
+
+        println!("{} | {}", patterns.join(" "), outputs.join(" "));
+        let faults: Vec<String> = faulty
+            .iter()
+            .zip(stuck_on.iter())
+            .map(|(&wire, &on)| {
+                format!(
+                    "wire {} stuck {}",
+                    alphabet.letter(wire),
+                    if on { "on" } else { "off" }
+                )
+            })
+            .collect();
+        let reading: String = shown
+            .iter()
+            .map(|&i| alphabet.symbols[i].as_str())
+            .collect();
+        eprintln!(
+            "{}: {} via {}{}{}",
+            line + 1,
+            reading,
+            format_wiring(alphabet, &wiring),
+            if faults.is_empty() { "" } else { ", " },
+            faults.join(", ")
+        );
+    }
+}
+
+struct GenerateOptions {
+    count: usize,
+    seed: u64,
+    digits: usize,
+    drop: usize,
+    faults: usize,
+}
+
 fn main() {
     let matches = App::new("day8")
         .version("1.0")
@@ -173,16 +797,195 @@ fn main() {
                 .short("p")
                 .long("part")
                 .value_name("PART")
-                .help("Which part")
+                .help("Which part (1, 2, decode, diagnose: finds stuck wires, not swapped ones, generate)")
                 .required(true),
         )
+        .arg(
+            Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("FILE")
+                .default_value("/Users/alexeyler/Development/aoc/2021/day8/src/data/signals.txt")
+                .help("Display observations, patterns | outputs per line (1, 2, decode, diagnose)"),
+        )
+        .arg(
+            Arg::with_name("alphabet")
+                .short("a")
+                .long("alphabet")
+                .value_name("ALPHABET")
+                .default_value("seven")
+                .help("seven, hex, fourteen, sixteen, or a file defining each symbol's segments"),
+        )
+        .arg(
+            Arg::with_name("count")
+                .short("n")
+                .long("count")
+                .value_name("N")
+                .default_value("10")
+                .help("# of displays to write (generate)"),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .short("s")
+                .long("seed")
+                .value_name("SEED")
+                .default_value("0")
+                .help("RNG seed (generate)"),
+        )
+        .arg(
+            Arg::with_name("digits")
+                .long("digits")
+                .value_name("N")
+                .default_value("4")
+                .help("# of outputs per display (generate)"),
+        )
+        .arg(
+            Arg::with_name("drop")
+                .long("drop")
+                .value_name("N")
+                .default_value("0")
+                .help("# of patterns to leave out of each display (generate)"),
+        )
+        .arg(
+            Arg::with_name("faults")
+                .long("faults")
+                .value_name("N")
+                .default_value("0")
+                .help("# of wires stuck on or off in each display (generate)"),
+        )
         .get_matches();
     let part = matches.value_of("part").unwrap();
-    let lines: Vec<String> =
-        common::read_file("/Users/alexeyler/Development/aoc/2021/day8/src/data/signals.txt");
+    // Everything but generate reads displays
+    let read_lines = || common::read_file(matches.value_of("input").unwrap());
+    let alphabet = Alphabet::load(matches.value_of("alphabet").unwrap());
     match part {
-        "1" => p1(&lines),
-        "2" => p2(&lines),
+        "1" => p1(&read_lines(), &alphabet),
+        "2" => p2(&read_lines(), &alphabet),
+        "decode" => decode_all(&read_lines(), &alphabet),
+        "diagnose" => diagnose_all(&read_lines(), &alphabet),
+        "generate" => generate(
+            &alphabet,
+            &GenerateOptions {
+                count: matches.value_of("count").unwrap().parse::<usize>().unwrap(),
+                seed: matches.value_of("seed").unwrap().parse::<u64>().unwrap(),
+                digits: matches
+                    .value_of("digits")
+                    .unwrap()
+                    .parse::<usize>()
+                    .unwrap(),
+                drop: matches.value_of("drop").unwrap().parse::<usize>().unwrap(),
+                faults: matches
+                    .value_of("faults")
+                    .unwrap()
+                    .parse::<usize>()
+                    .unwrap(),
+            },
+        ),
         _ => eprintln!("Bad arg value: {}", part),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_output_line(alphabet: &Alphabet, line: &str) -> Result<String, String> {
+        let lines = vec![line.to_string()];
+        let displays = get_displays(&lines);
+        get_output(alphabet, &displays[0]).map(|reading| reading.concat())
+    }
+
+    #[test]
+    fn reads_the_sample() {
+        let alphabet = Alphabet::load("seven");
+        let line =
+            "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | cdfeb fcadb cdfeb cdbaf";
+        assert_eq!(get_output_line(&alphabet, line).unwrap(), "5353");
+    }
+
+    #[test]
+    fn checks_every_seven_segment_wiring() {
+        // Any wiring at all fits, but they all read a lone lit pair as 1
+        let alphabet = Alphabet::load("seven");
+        assert_eq!(get_output_line(&alphabet, "| cf").unwrap(), "1");
+        assert!(get_output_line(&alphabet, "| abcde").is_err());
+        // Far too many sixteen segment wirings to check them all
+        let alphabet = Alphabet::load("sixteen");
+        assert!(get_output_line(&alphabet, "| cd").is_err());
+    }
+
+    // Every symbol shown through the straight wiring reads back as itself
+    fn assert_reads_own_symbols(name: &str) {
+        let alphabet = Alphabet::load(name);
+        let patterns: Vec<String> = alphabet
+            .shapes
+            .iter()
+            .map(|&shape| {
+                (0..alphabet.len())
+                    .filter(|&segment| shape & 1 << segment != 0)
+                    .map(|segment| alphabet.letter(segment))
+                    .collect()
+            })
+            .collect();
+        let line = format!("{} | {}", patterns.join(" "), patterns.join(" "));
+        assert_eq!(
+            get_output_line(&alphabet, &line).unwrap(),
+            alphabet.symbols.concat(),
+            "{}",
+            name
+        );
+    }
+
+    #[test]
+    fn built_in_alphabets_read_their_own_symbols() {
+        for name in &["seven", "hex", "fourteen", "sixteen"] {
+            assert_reads_own_symbols(name);
+        }
+    }
+
+    #[test]
+    fn counts_letters_not_bytes() {
+        let alphabet = Alphabet::parse("segments äöü\n1 ä\n2 äö\n3 äöü");
+        let lines = vec!["ä äö äöü | öü ü äöü".to_string()];
+        assert_eq!(get_easy_count(&lines, &alphabet), 3);
+    }
+
+    #[test]
+    fn renders_segments_in_any_order() {
+        let shuffled =
+            Alphabet::parse(&SEVEN_SEGMENT.replace("segments abcdefg", "segments gfedcba"));
+        let seven = Alphabet::load("seven");
+        let reading = vec!["0", "1", "2", "3", "4", "5", "6", "7", "8", "9"];
+        assert_eq!(render(&shuffled, &reading), render(&seven, &reading));
+        assert!(render(&Alphabet::load("fourteen"), &reading).is_none());
+    }
+
+    #[test]
+    fn reads_dark_patterns() {
+        let alphabet = Alphabet::load("seven");
+        let lines = vec!["- cf | cf -".to_string()];
+        let displays = get_displays(&lines);
+        assert_eq!(displays[0].patterns, vec!["", "cf"]);
+        assert_eq!(displays[0].outputs, vec!["cf", ""]);
+        assert_eq!(get_easy_count(&lines, &alphabet), 1);
+    }
+
+    #[test]
+    fn segments_can_be_any_letter() {
+        let alphabet = Alphabet::parse("segments 0AZ_\n1 0\n2 AZ\n3 0A_");
+        assert_eq!(alphabet.get_mask("_0"), 0b1001);
+        assert_eq!(get_output_line(&alphabet, "Z _0 A_Z | 0_ Z").unwrap(), "21");
+    }
+
+    #[test]
+    fn stops_after_enough_diagnoses() {
+        // Any 6 of the 16 wires could be the stuck ones
+        let alphabet = Alphabet::load("sixteen");
+        let lines = vec!["abcdefghijklmnop | abcdefghijklmnop".to_string()];
+        let diagnoses = diagnose(&alphabet, &get_displays(&lines)[0]);
+        assert_eq!(diagnoses.len(), MAX_CANDIDATES);
+        assert!(diagnoses
+            .iter()
+            .all(|d| d.faulty.count_ones() == 6 && d.more));
+    }
+}
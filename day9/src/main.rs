@@ -1,201 +1,458 @@
-extern crate queues;
 use clap::App;
 use clap::Arg;
 use colored::*;
-use queues::*;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
 
-#[derive(Clone, Copy, Debug)]
-struct Point {
-    height: usize,
-    low_point: bool,
-    row: usize,
-    col: usize,
-    basin: usize,
+// How cells connect, what counts as a low point and how high walls start
+struct MapOptions {
+    diagonal: bool,
+    plateaus: bool,
+    wall: u8,
 }
 
-impl PartialEq for Point {
-    fn eq(&self, other: &Self) -> bool {
-        self.row == other.row && self.col == other.col
+// Heights in row-major order, one byte per cell, so even 10k x 10k maps stay small
+struct Heightmap {
+    rows: usize,
+    cols: usize,
+    heights: Vec<u8>,
+    diagonal: bool,
+    wall: u8,
+}
+
+impl Heightmap {
+    // Cells next to cell i, up, down, left and right, plus the corners when diagonals connect
+    fn neighbors(&self, i: usize) -> impl Iterator<Item = usize> {
+        let (r, c) = (i / self.cols, i % self.cols);
+        let cols = self.cols;
+        let (up, down, left, right) = (r > 0, r + 1 < self.rows, c > 0, c + 1 < cols);
+        let diagonal = self.diagonal;
+        IntoIterator::into_iter([
+            up.then(|| i - cols),
+            down.then(|| i + cols),
+            left.then(|| i - 1),
+            right.then(|| i + 1),
+            (diagonal && up && left).then(|| i - cols - 1),
+            (diagonal && up && right).then(|| i - cols + 1),
+            (diagonal && down && left).then(|| i + cols - 1),
+            (diagonal && down && right).then(|| i + cols + 1),
+        ])
+        .flatten()
+    }
+
+    fn is_wall(&self, i: usize) -> bool {
+        self.heights[i] >= self.wall
     }
+
+    // All eight cells around cell i
+    fn surrounding(&self, i: usize) -> impl Iterator<Item = usize> {
+        let (r, c) = (i / self.cols, i % self.cols);
+        let (rows, cols) = (self.rows as isize, self.cols as isize);
+        (-1isize..2)
+            .flat_map(|dr| (-1isize..2).map(move |dc| (dr, dc)))
+            .filter(|&d| d != (0, 0))
+            .map(move |(dr, dc)| (r as isize + dr, c as isize + dc))
+            .filter(move |&(r, c)| r >= 0 && r < rows && c >= 0 && c < cols)
+            .map(move |(r, c)| (r * cols + c) as usize)
+    }
+}
+
+// Every cell's basin, numbered from 1 in the order their first cells appear (0 for walls), how
+// big each basin is (sizes[0] is unused) and where the low points are
+struct Basins {
+    labels: Vec<u32>,
+    sizes: Vec<usize>,
+    low_points: Vec<usize>,
 }
 
-static COLORS: [(u8, u8, u8); 9] = [
-    (255, 0, 0),
-    (0, 255, 0),
-    (0, 0, 255),
-    (255, 255, 0),
-    (0, 255, 255),
-    (255, 0, 255),
-    (192, 192, 192),
-    (128, 0, 0),
-    (0, 128, 0),
-];
+const WALL_COLOR: (u8, u8, u8) = (60, 60, 60);
+const LOW_POINT_COLOR: (u8, u8, u8) = (255, 255, 255);
 
-fn get_dimensions(map: &Vec<Vec<Point>>) -> (usize, usize) {
-    (map.len(), map[0].len())
+fn get_risklevel(heightmap: &Heightmap, basins: &Basins) -> usize {
+    basins
+        .low_points
+        .iter()
+        .map(|&i| heightmap.heights[i] as usize + 1)
+        .sum()
 }
 
-fn get_risklevel(map: &Vec<Vec<Point>>) -> usize {
-    let mut risklevel = 0;
-    for row in map {
-        for point in row {
-            if point.low_point {
-                risklevel += point.height + 1;
+// Basins that face each other across a wall, diagonals included, as lists of neighboring ids
+fn get_basin_graph(heightmap: &Heightmap, basins: &Basins) -> Vec<Vec<u32>> {
+    let mut edges: HashSet<(u32, u32)> = HashSet::new();
+    for i in (0..heightmap.heights.len()).filter(|&i| basins.labels[i] == 0) {
+        let mut around: Vec<u32> = heightmap
+            .surrounding(i)
+            .map(|j| basins.labels[j])
+            .filter(|&label| label > 0)
+            .collect();
+        around.sort_unstable();
+        around.dedup();
+        for (k, &a) in around.iter().enumerate() {
+            for &b in &around[k + 1..] {
+                edges.insert((a, b));
             }
         }
     }
-    risklevel
+    let mut graph: Vec<Vec<u32>> = vec![Vec::new(); basins.sizes.len()];
+    for (a, b) in edges {
+        graph[a as usize].push(b);
+        graph[b as usize].push(a);
+    }
+    graph
+}
+
+// Greedy coloring, basins with the most neighbors first, taking the lowest color no neighbor
+// has. Returns each basin's color (index 0 is unused) and how many colors it took.
+fn color_basins(graph: &[Vec<u32>]) -> (Vec<usize>, usize) {
+    let mut order: Vec<usize> = (1..graph.len()).collect();
+    order.sort_by_key(|&basin| std::cmp::Reverse(graph[basin].len()));
+    let mut colors = vec![usize::MAX; graph.len()];
+    let mut count = 0;
+    for basin in order {
+        let taken: HashSet<usize> = graph[basin].iter().map(|&b| colors[b as usize]).collect();
+        let color = (0..).find(|c| !taken.contains(c)).unwrap();
+        colors[basin] = color;
+        count = count.max(color + 1);
+    }
+    (colors, count)
+}
+
+// As many distinct colors as it takes: hues spaced by the golden angle never bunch up
+fn get_palette_color(color: usize) -> (u8, u8, u8) {
+    let hue = (color as f64 * 0.618_033_988_75).fract() * 6.0;
+    let (saturation, value) = (0.75, 1.0);
+    let chroma = value * saturation;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as usize {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = value - chroma;
+    let to_byte = |v: f64| ((v + m) * 255.0).round() as u8;
+    (to_byte(r), to_byte(g), to_byte(b))
 }
 
-fn print_heightmap(map: &Vec<Vec<Point>>) {
-    for row in map {
-        for point in row {
+// Each basin's palette color, so neighboring basins never match
+fn get_basin_colors(heightmap: &Heightmap, basins: &Basins) -> (Vec<(u8, u8, u8)>, usize) {
+    let (colors, count) = color_basins(&get_basin_graph(heightmap, basins));
+    (
+        colors
+            .iter()
+            .map(|&color| get_palette_color(color))
+            .collect(),
+        count,
+    )
+}
+
+// Colors every cell in a basin, and low points on white. Without basins, only the low points
+// are colored.
+fn print_heightmap(heightmap: &Heightmap, basins: &Basins, show_basins: bool) {
+    let mut low_point = vec![false; heightmap.heights.len()];
+    for &i in &basins.low_points {
+        low_point[i] = true;
+    }
+    let (basin_colors, _) = get_basin_colors(heightmap, basins);
+    for r in 0..heightmap.rows {
+        for c in 0..heightmap.cols {
+            let i = r * heightmap.cols + c;
+            let height = heightmap.heights[i].to_string();
+            let true_color = basin_colors[basins.labels[i] as usize];
             print!(
                 "{}",
-                if point.low_point {
-                    let true_color = COLORS[point.basin - 1];
-                    point.height.to_string().on_white().truecolor(
-                        true_color.0,
-                        true_color.1,
-                        true_color.2,
-                    )
-                } else if point.basin > 0 {
-                    let true_color = COLORS[point.basin - 1];
-                    point
-                        .height
-                        .to_string()
+                if low_point[i] {
+                    height
+                        .on_white()
                         .truecolor(true_color.0, true_color.1, true_color.2)
+                } else if show_basins && basins.labels[i] > 0 {
+                    height.truecolor(true_color.0, true_color.1, true_color.2)
                 } else {
-                    point.height.to_string().white()
+                    height.white()
                 }
             )
         }
         println!();
     }
-    println!("Risk level: {}", get_risklevel(map));
-}
-
-fn make_heightmap(lines: &Vec<String>) -> Vec<Vec<Point>> {
-    let mut map: Vec<Vec<Point>> = Vec::new();
-    for r in 0..lines.len() {
-        let line = &lines[r];
-        let mut row: Vec<Point> = Vec::new();
-        for c in 0..line.len() {
-            let height = (line.as_bytes()[c] as char).to_digit(10).unwrap() as usize;
-            row.push(Point {
-                height,
-                low_point: false,
-                row: r,
-                col: c,
-                basin: 0,
-            });
-        }
-        map.push(row);
-    }
-    map
-}
-
-fn calc_low_points(heightmap: &mut Vec<Vec<Point>>) -> Vec<Point> {
-    let (rows, cols) = get_dimensions(&heightmap);
-    let mut low_points: Vec<Point> = Vec::new();
-    let mut color_index: usize = 1;
-    for r in 0..rows {
-        for c in 0..cols {
-            let point = &heightmap[r][c];
-
-            if (r == 0 || point.height < heightmap[r - 1][c].height)
-                && (r + 1 >= rows || point.height < heightmap[r + 1][c].height)
-                && (c == 0 || point.height < heightmap[r][c - 1].height)
-                && (c + 1 >= cols || point.height < heightmap[r][c + 1].height)
-            {
-                let mut_point = &mut heightmap[r][c];
-                mut_point.low_point = true;
-                mut_point.basin = color_index;
-                color_index += 1;
-                if color_index > 9 {
-                    color_index = 1;
+    println!("Risk level: {}", get_risklevel(heightmap, basins));
+}
+
+fn make_heightmap(lines: &[String], options: &MapOptions) -> Heightmap {
+    let lines: Vec<&String> = lines.iter().filter(|line| !line.is_empty()).collect();
+    let cols = lines[0].len();
+    let mut heights: Vec<u8> = Vec::with_capacity(lines.len() * cols);
+    for line in &lines {
+        if line.len() != cols {
+            panic!("Every row needs {} heights", cols);
+        }
+        heights.extend(line.bytes().map(|b| match b {
+            b'0'..=b'9' => b - b'0',
+            _ => panic!("Bad height {}", b as char),
+        }));
+    }
+    Heightmap {
+        rows: lines.len(),
+        cols,
+        heights,
+        diagonal: options.diagonal,
+        wall: options.wall,
+    }
+}
+
+// Follows parents up to the root, pointing each cell passed at its grandparent on the way
+fn find(parent: &mut [u32], mut i: u32) -> u32 {
+    while parent[i as usize] != i {
+        let grandparent = parent[parent[i as usize] as usize];
+        parent[i as usize] = grandparent;
+        i = grandparent;
+    }
+    i
+}
+
+// Flat regions of equal height with nothing lower around them, one cell (the first) for each.
+// A single cell lower than all its neighbors is just the smallest plateau. Walls aren't in any
+// basin, so they're never low points.
+fn get_plateau_minima(heightmap: &Heightmap) -> Vec<usize> {
+    let heights = &heightmap.heights;
+    let mut seen = vec![false; heights.len()];
+    let mut stack: Vec<usize> = Vec::new();
+    let mut minima: Vec<usize> = Vec::new();
+    for start in 0..heights.len() {
+        if seen[start] || heightmap.is_wall(start) {
+            continue;
+        }
+        let height = heights[start];
+        let mut lowest = true;
+        seen[start] = true;
+        stack.push(start);
+        while let Some(i) = stack.pop() {
+            for neighbor in heightmap.neighbors(i) {
+                if heights[neighbor] < height {
+                    lowest = false;
+                } else if heights[neighbor] == height && !seen[neighbor] {
+                    seen[neighbor] = true;
+                    stack.push(neighbor);
                 }
-                low_points.push(heightmap[r][c]);
             }
         }
+        if lowest {
+            minima.push(start);
+        }
     }
-    low_points
-}
-
-fn calc_basin(height_map: &mut Vec<Vec<Point>>, low_point: &Point) -> i32 {
-    let (rows, cols) = get_dimensions(height_map);
-    let mut seen: Vec<Point> = Vec::new();
-    let mut queue: Queue<Point> = queue![];
-    queue.add(*low_point).unwrap();
-
-    let mut basin_size = 0;
-    while queue.size() > 0 {
-        let current_point = queue.remove().unwrap();
-        if !seen.contains(&current_point) {
-            seen.push(current_point);
-            if current_point.height < 9 {
-                let mut_point = &mut height_map[current_point.row][current_point.col];
-                mut_point.basin = low_point.basin;
-                basin_size += 1;
-                if current_point.row != 0
-                    && height_map[current_point.row - 1][current_point.col].height != 9
-                {
-                    queue
-                        .add(height_map[current_point.row - 1][current_point.col])
-                        .unwrap();
-                }
-                if current_point.row < rows - 1
-                    && height_map[current_point.row + 1][current_point.col].height != 9
-                {
-                    queue
-                        .add(height_map[current_point.row + 1][current_point.col])
-                        .unwrap();
-                }
-                if current_point.col != 0
-                    && height_map[current_point.row][current_point.col - 1].height != 9
-                {
-                    queue
-                        .add(height_map[current_point.row][current_point.col - 1])
-                        .unwrap();
-                }
-                if current_point.col < cols - 1
-                    && height_map[current_point.row][current_point.col + 1].height != 9
-                {
-                    queue
-                        .add(height_map[current_point.row][current_point.col + 1])
-                        .unwrap();
+    minima
+}
+
+// One pass over the map with a union-find: each cell joins the basins of the cells before it
+// (above and to its left, and the upper corners when diagonals connect), and low points are
+// picked out along the way. The lower root always wins a union, so parents never point
+// forward, and a second pass can swap roots for basin ids in place.
+fn label_basins(heightmap: &Heightmap, plateaus: bool) -> Basins {
+    let n = heightmap.heights.len();
+    let cols = heightmap.cols;
+    if n as u64 * 2 >= u32::MAX as u64 {
+        panic!("{} cells is too many to label", n);
+    }
+    let heights = &heightmap.heights;
+    let mut parent: Vec<u32> = Vec::with_capacity(n);
+    let mut low_points: Vec<usize> = Vec::new();
+    for i in 0..n {
+        if !plateaus
+            && !heightmap.is_wall(i)
+            && heightmap
+                .neighbors(i)
+                .all(|neighbor| heights[i] < heights[neighbor])
+        {
+            low_points.push(i);
+        }
+        parent.push(i as u32);
+        if heightmap.is_wall(i) {
+            continue;
+        }
+        let (up, left, right) = (i >= cols, i % cols > 0, i % cols + 1 < cols);
+        let diagonal = heightmap.diagonal;
+        for neighbor in [
+            up.then(|| i - cols),
+            left.then(|| i - 1),
+            (diagonal && up && left).then(|| i - cols - 1),
+            (diagonal && up && right).then(|| i - cols + 1),
+        ] {
+            match neighbor {
+                Some(neighbor) if !heightmap.is_wall(neighbor) => {
+                    let a = find(&mut parent, i as u32);
+                    let b = find(&mut parent, neighbor as u32);
+                    parent[a.max(b) as usize] = a.min(b);
                 }
+                _ => (),
             }
         }
     }
-    basin_size
+
+    // Ids are stored as n + id until the pass is done, so they can't be mistaken for cells
+    let mut sizes: Vec<usize> = vec![0];
+    for i in 0..n {
+        if heightmap.is_wall(i) {
+            parent[i] = n as u32;
+        } else if parent[i] == i as u32 {
+            parent[i] = (n + sizes.len()) as u32;
+            sizes.push(1);
+        } else {
+            parent[i] = parent[parent[i] as usize];
+            sizes[parent[i] as usize - n] += 1;
+        }
+    }
+    for label in parent.iter_mut() {
+        *label -= n as u32;
+    }
+    if plateaus {
+        low_points = get_plateau_minima(heightmap);
+    }
+
+    Basins {
+        labels: parent,
+        sizes,
+        low_points,
+    }
 }
 
-fn p1(lines: &Vec<String>) {
-    let mut heightmap = make_heightmap(&lines);
-    calc_low_points(&mut heightmap);
-    print_heightmap(&heightmap);
+// The sizes of the basins part 2 counts. Labeling finds every walled-off region, but like the
+// original flood fill from each low point, only regions with a low point in them count: a flat
+// region with no strict low point isn't a basin unless plateaus are on.
+fn get_basin_sizes(basins: &Basins) -> Vec<usize> {
+    let mut labels: Vec<u32> = basins
+        .low_points
+        .iter()
+        .map(|&i| basins.labels[i])
+        .filter(|&label| label > 0)
+        .collect();
+    labels.sort_unstable();
+    labels.dedup();
+    labels
+        .iter()
+        .map(|&label| basins.sizes[label as usize])
+        .collect()
 }
 
-fn p2(lines: &Vec<String>) {
-    let mut heightmap = make_heightmap(&lines);
-    let low_points = calc_low_points(&mut heightmap);
-    let mut basin_sizes: Vec<i32> = Vec::new();
-    for low_point in low_points {
-        let basin_size = calc_basin(&mut heightmap, &low_point);
-        basin_sizes.push(basin_size);
+fn p1(lines: &[String], options: &MapOptions, quiet: bool) {
+    let heightmap = make_heightmap(lines, options);
+    let basins = label_basins(&heightmap, options.plateaus);
+    if quiet {
+        println!("Risk level: {}", get_risklevel(&heightmap, &basins));
+    } else {
+        print_heightmap(&heightmap, &basins, false);
     }
+}
+
+fn p2(lines: &[String], options: &MapOptions, quiet: bool) {
+    let heightmap = make_heightmap(lines, options);
+    let basins = label_basins(&heightmap, options.plateaus);
+    let mut basin_sizes = get_basin_sizes(&basins);
     basin_sizes.sort_by(|a, b| b.cmp(a));
-    print_heightmap(&heightmap);
+    if !quiet {
+        print_heightmap(&heightmap, &basins, true);
+    }
+    // Diagonals and high walls can merge the map into fewer than three basins
+    let largest = &basin_sizes[..basin_sizes.len().min(3)];
     println!(
-        "{} * {} * {} = {}",
-        basin_sizes[0],
-        basin_sizes[1],
-        basin_sizes[2],
-        basin_sizes[0] * basin_sizes[1] * basin_sizes[2]
+        "{} = {}",
+        largest
+            .iter()
+            .map(|size| size.to_string())
+            .collect::<Vec<String>>()
+            .join(" * "),
+        largest.iter().product::<usize>()
     )
 }
 
+// RGB pixels for the map, `scale` pixels per cell. Basins get their palette color, darker the
+// lower they are, walls are dark gray and low points get a white dot.
+fn get_pixels(heightmap: &Heightmap, basins: &Basins, scale: usize) -> (Vec<u8>, usize) {
+    let (basin_colors, _) = get_basin_colors(heightmap, basins);
+    let mut low_point = vec![false; heightmap.heights.len()];
+    for &i in &basins.low_points {
+        low_point[i] = true;
+    }
+    // The middle third of a cell, or all of it when that would be nothing
+    let dot = if scale >= 3 {
+        scale / 3..scale - scale / 3
+    } else {
+        0..scale
+    };
+    let width = heightmap.cols * scale;
+    let mut pixels = vec![0u8; width * heightmap.rows * scale * 3];
+    for (i, &height) in heightmap.heights.iter().enumerate() {
+        let label = basins.labels[i] as usize;
+        let color = if label == 0 {
+            WALL_COLOR
+        } else {
+            let brightness = 0.35 + 0.65 * height as f64 / (heightmap.wall.max(2) - 1) as f64;
+            let (r, g, b) = basin_colors[label];
+            let dim = |v: u8| (v as f64 * brightness.min(1.0)).round() as u8;
+            (dim(r), dim(g), dim(b))
+        };
+        let (row, col) = (i / heightmap.cols * scale, i % heightmap.cols * scale);
+        for y in 0..scale {
+            for x in 0..scale {
+                let (r, g, b) = if low_point[i] && dot.contains(&y) && dot.contains(&x) {
+                    LOW_POINT_COLOR
+                } else {
+                    color
+                };
+                let p = ((row + y) * width + col + x) * 3;
+                pixels[p..p + 3].copy_from_slice(&[r, g, b]);
+            }
+        }
+    }
+    (pixels, width)
+}
+
+// Writes a PNG for a .png path, otherwise a binary PPM
+fn write_image(pixels: &[u8], width: usize, path: &str) -> io::Result<()> {
+    let height = pixels.len() / 3 / width;
+    let mut file = BufWriter::new(File::create(path)?);
+    if path.ends_with(".png") {
+        let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let to_io = |e: png::EncodingError| io::Error::other(e);
+        let mut writer = encoder.write_header().map_err(to_io)?;
+        writer.write_image_data(pixels).map_err(to_io)?;
+        writer.finish().map_err(to_io)
+    } else {
+        write!(file, "P6\n{} {}\n255\n", width, height)?;
+        file.write_all(pixels)?;
+        file.flush()
+    }
+}
+
+fn image(lines: &[String], options: &MapOptions, output: &str, scale: usize) {
+    let heightmap = make_heightmap(lines, options);
+    let bytes = (heightmap.heights.len() * 3)
+        .checked_mul(scale)
+        .and_then(|bytes| bytes.checked_mul(scale));
+    let side = heightmap.rows.max(heightmap.cols) as u64 * scale as u64;
+    if bytes.is_none_or(|bytes| bytes > isize::MAX as usize) || side > u32::MAX as u64 {
+        eprintln!("Bad arg value: scale {} makes too big an image", scale);
+        return;
+    }
+    let basins = label_basins(&heightmap, options.plateaus);
+    let (_, colors) = get_basin_colors(&heightmap, &basins);
+    let (pixels, width) = get_pixels(&heightmap, &basins, scale);
+    match write_image(&pixels, width, output) {
+        Ok(()) => println!(
+            "Wrote {}x{} image to {} ({} basins in {} colors)",
+            width,
+            heightmap.rows * scale,
+            output,
+            basins.sizes.len() - 1,
+            colors
+        ),
+        Err(e) => eprintln!("Couldn't write {}: {}", output, e),
+    }
+}
+
 fn main() {
     let matches = App::new("day9")
         .version("1.0")
@@ -206,16 +463,207 @@ fn main() {
                 .short("p")
                 .long("part")
                 .value_name("PART")
-                .help("Which part")
+                .help("Which part (1, 2, image)")
                 .required(true),
         )
+        .arg(
+            Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("FILE")
+                .default_value("/Users/alexeyler/Development/aoc/2021/day9/src/data/heightmap.txt")
+                .help("Heightmap, one row of digits per line"),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .takes_value(false)
+                .help("Skip printing the map"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("FILE")
+                .default_value("basins.ppm")
+                .help("Image to write, PNG for a .png path and PPM otherwise (image)"),
+        )
+        .arg(
+            Arg::with_name("scale")
+                .long("scale")
+                .value_name("N")
+                .default_value("4")
+                .help("Pixels per cell, each way (image)"),
+        )
+        .arg(
+            Arg::with_name("diagonal")
+                .short("d")
+                .long("diagonal")
+                .takes_value(false)
+                .help("Connect cells diagonally too, for low points and basins"),
+        )
+        .arg(
+            Arg::with_name("plateaus")
+                .long("plateaus")
+                .takes_value(false)
+                .help("Count flat regions lower than everything around them as low points"),
+        )
+        .arg(
+            Arg::with_name("wall")
+                .short("w")
+                .long("wall")
+                .value_name("HEIGHT")
+                .default_value("9")
+                .help("Cells this high or higher wall basins off (1-10, 10 for no walls)"),
+        )
         .get_matches();
     let part = matches.value_of("part").unwrap();
-    let lines: Vec<String> =
-        common::read_file("/Users/alexeyler/Development/aoc/2021/day9/src/data/heightmap.txt");
+    let quiet = matches.is_present("quiet");
+    let wall = match matches.value_of("wall").unwrap().parse::<u8>() {
+        Ok(wall) if (1..=10).contains(&wall) => wall,
+        _ => {
+            eprintln!("Bad arg value: wall height must be 1-10");
+            return;
+        }
+    };
+    let options = MapOptions {
+        diagonal: matches.is_present("diagonal"),
+        plateaus: matches.is_present("plateaus"),
+        wall,
+    };
+    let lines: Vec<String> = common::read_file(matches.value_of("input").unwrap());
     match part {
-        "1" => p1(&lines),
-        "2" => p2(&lines),
+        "1" => p1(&lines, &options, quiet),
+        "2" => p2(&lines, &options, quiet),
+        "image" => match matches.value_of("scale").unwrap().parse::<usize>() {
+            Ok(scale) if scale > 0 => {
+                image(&lines, &options, matches.value_of("output").unwrap(), scale)
+            }
+            _ => eprintln!("Bad arg value: scale must be a positive integer"),
+        },
         _ => eprintln!("Bad arg value: {}", part),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    const STRICT: MapOptions = MapOptions {
+        diagonal: false,
+        plateaus: false,
+        wall: 9,
+    };
+
+    fn get_map(rows: &[&str], options: &MapOptions) -> Heightmap {
+        let lines: Vec<String> = rows.iter().map(|row| row.to_string()).collect();
+        make_heightmap(&lines, options)
+    }
+
+    // The original approach: flood fill out from each low point, stopping at walls
+    fn flood_fill(heightmap: &Heightmap, start: usize, seen: &mut [bool]) -> usize {
+        let mut queue: VecDeque<usize> = VecDeque::new();
+        queue.push_back(start);
+        seen[start] = true;
+        let mut basin_size = 0;
+        while let Some(i) = queue.pop_front() {
+            basin_size += 1;
+            for neighbor in heightmap.neighbors(i) {
+                if !seen[neighbor] && !heightmap.is_wall(neighbor) {
+                    seen[neighbor] = true;
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+        basin_size
+    }
+
+    // Labeling and flood filling give the same basins, and each low point's basin the same size
+    fn assert_matches_flood_fill(heightmap: &Heightmap, plateaus: bool) -> Vec<usize> {
+        let basins = label_basins(heightmap, plateaus);
+        let mut seen = vec![false; heightmap.heights.len()];
+        let mut flooded: Vec<usize> = Vec::new();
+        for &low_point in &basins.low_points {
+            if seen[low_point] || heightmap.is_wall(low_point) {
+                continue;
+            }
+            let size = flood_fill(heightmap, low_point, &mut seen);
+            assert_eq!(size, basins.sizes[basins.labels[low_point] as usize]);
+            flooded.push(size);
+        }
+        let mut labeled = get_basin_sizes(&basins);
+        flooded.sort_unstable();
+        labeled.sort_unstable();
+        assert_eq!(labeled, flooded);
+        labeled
+    }
+
+    #[test]
+    fn sample() {
+        let path = format!("{}/src/data/test.txt", env!("CARGO_MANIFEST_DIR"));
+        for options in [
+            STRICT,
+            MapOptions {
+                diagonal: true,
+                ..STRICT
+            },
+        ] {
+            let heightmap = make_heightmap(&common::read_file(&path), &options);
+            assert_matches_flood_fill(&heightmap, false);
+            assert_matches_flood_fill(&heightmap, true);
+        }
+        let heightmap = make_heightmap(&common::read_file(&path), &STRICT);
+        let basins = label_basins(&heightmap, false);
+        assert_eq!(get_risklevel(&heightmap, &basins), 15);
+        assert_eq!(
+            assert_matches_flood_fill(&heightmap, false),
+            vec![3, 9, 9, 14]
+        );
+    }
+
+    #[test]
+    fn plateau() {
+        let heightmap = get_map(&["3333", "3113", "3113", "3339", "9995"], &STRICT);
+        // Only the 5 is lower than all its neighbors, so the 1s aren't a basin...
+        assert_eq!(assert_matches_flood_fill(&heightmap, false), vec![1]);
+        // ...unless the flat region of 1s counts as a low point
+        assert_eq!(assert_matches_flood_fill(&heightmap, true), vec![1, 15]);
+    }
+
+    #[test]
+    fn walls_are_not_low_points() {
+        let options = MapOptions { wall: 3, ..STRICT };
+        let heightmap = get_map(&["4433", "4433", "3321"], &options);
+        for plateaus in [true, false] {
+            let basins = label_basins(&heightmap, plateaus);
+            // The 4s and the 3s are flat and nothing's lower around them, but they're walls
+            assert_eq!(basins.low_points, vec![11]);
+            assert_eq!(get_risklevel(&heightmap, &basins), 2);
+            assert_eq!(assert_matches_flood_fill(&heightmap, plateaus), vec![2]);
+        }
+        // The 5 is lower than everything around it, but it's a wall too
+        let heightmap = get_map(&["565", "666"], &options);
+        for plateaus in [true, false] {
+            let basins = label_basins(&heightmap, plateaus);
+            assert!(basins.low_points.is_empty());
+            assert_eq!(get_risklevel(&heightmap, &basins), 0);
+        }
+    }
+
+    #[test]
+    fn one_row() {
+        let heightmap = get_map(&["2199943210"], &STRICT);
+        assert_eq!(assert_matches_flood_fill(&heightmap, false), vec![2, 5]);
+        assert_matches_flood_fill(&heightmap, true);
+    }
+
+    #[test]
+    fn one_column() {
+        let rows = ["2", "1", "9", "9", "9", "4", "3", "2", "1", "0"];
+        let heightmap = get_map(&rows, &STRICT);
+        assert_eq!(assert_matches_flood_fill(&heightmap, false), vec![2, 5]);
+        assert_matches_flood_fill(&heightmap, true);
+    }
+}